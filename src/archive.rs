@@ -0,0 +1,227 @@
+use super::checksum;
+
+// ROM sets are usually passed around as ZIP files, and sometimes gzipped. We unpack them ourselves, so it works the same
+// for fetched links and local files. Only "stored" and "deflate" compression are supported, which is what everyone uses.
+
+const MAX_SIZE: usize = 0x200000; //Twice the biggest cartridge, leaving room for .chf headers. Anything bigger is a zip bomb.
+
+pub(super) struct Entry {
+  pub name: String,
+  pub bytes: Vec<u8>,
+}
+
+/// Returns the files inside, or None if the bytes are not an archive we know.
+/// Damaged files inside a ZIP are skipped.
+pub(super) fn unpack(bytes: &[u8]) -> Option<Vec<Entry>> {
+  if bytes.starts_with(b"PK\x03\x04") || bytes.starts_with(b"PK\x05\x06") {
+    Some(unpack_zip(bytes))
+  } else if bytes.starts_with(&[0x1F, 0x8B]) {
+    Some(unpack_gzip(bytes).into_iter().collect())
+  } else {
+    None
+  }
+}
+
+fn unpack_zip(bytes: &[u8]) -> Vec<Entry> {
+  let mut entries = vec![];
+  //The end of central directory record is at the end, followed by a comment of up to 65535 bytes.
+  let end_offset = match (0..=bytes.len().saturating_sub(22)).rev().take(65536 + 22).find(|&offset| read_u32(bytes, offset) == Some(0x06054B50)) {
+    Some(offset) => offset,
+    None => return entries,
+  };
+  let entry_count = read_u16(bytes, end_offset + 10).unwrap_or(0);
+  let mut offset = read_u32(bytes, end_offset + 16).unwrap_or(0) as usize;
+
+  for _ in 0..entry_count {
+    if read_u32(bytes, offset) != Some(0x02014B50) {
+      break;
+    }
+    let header = (|| {
+      let method = read_u16(bytes, offset + 10)?;
+      let crc = read_u32(bytes, offset + 16)?;
+      let compressed_size = read_u32(bytes, offset + 20)? as usize;
+      let name_length = read_u16(bytes, offset + 28)? as usize;
+      let extra_length = read_u16(bytes, offset + 30)? as usize;
+      let comment_length = read_u16(bytes, offset + 32)? as usize;
+      let local_offset = read_u32(bytes, offset + 42)? as usize;
+      let name = String::from_utf8_lossy(bytes.get(offset + 46..offset + 46 + name_length)?).into_owned();
+      Some((method, crc, compressed_size, local_offset, name, 46 + name_length + extra_length + comment_length))
+    })();
+    let (method, crc, compressed_size, local_offset, name, header_length) = match header {
+      Some(header) => header,
+      None => break,
+    };
+    offset += header_length;
+
+    if name.ends_with('/') {
+      continue; //Directory
+    }
+    if let Some(data) = read_local_file(bytes, local_offset, compressed_size) {
+      let contents = match method {
+        0 => Some(data.to_vec()),
+        8 => inflate(data),
+        _ => None,
+      };
+      if let Some(contents) = contents {
+        if checksum::crc32(&contents) == crc {
+          entries.push(Entry { name, bytes: contents });
+        }
+      }
+    }
+  }
+  entries
+}
+
+/// The local header repeats the name and has its own extra field, so its length has to be read again.
+fn read_local_file(bytes: &[u8], offset: usize, compressed_size: usize) -> Option<&[u8]> {
+  if read_u32(bytes, offset)? != 0x04034B50 {
+    return None;
+  }
+  let name_length = read_u16(bytes, offset + 26)? as usize;
+  let extra_length = read_u16(bytes, offset + 28)? as usize;
+  let start = offset + 30 + name_length + extra_length;
+  bytes.get(start..start + compressed_size)
+}
+
+fn unpack_gzip(bytes: &[u8]) -> Option<Entry> {
+  const FHCRC: u8 = 0x02;
+  const FEXTRA: u8 = 0x04;
+  const FNAME: u8 = 0x08;
+  const FCOMMENT: u8 = 0x10;
+
+  if *bytes.get(2)? != 8 {
+    return None;  //Only deflate exists
+  }
+  let flags = *bytes.get(3)?;
+  let mut offset = 10;
+  if flags & FEXTRA != 0 {
+    offset += 2 + read_u16(bytes, offset)? as usize;
+  }
+  let mut name = String::from("rom.bin");
+  if flags & FNAME != 0 {
+    let length = bytes.get(offset..)?.iter().position(|&byte| byte == 0)?;
+    name = String::from_utf8_lossy(&bytes[offset..offset + length]).into_owned();
+    offset += length + 1;
+  }
+  if flags & FCOMMENT != 0 {
+    offset += bytes.get(offset..)?.iter().position(|&byte| byte == 0)? + 1;
+  }
+  if flags & FHCRC != 0 {
+    offset += 2;
+  }
+
+  //The file ends with the CRC and the size of the contents.
+  let trailer = bytes.len().checked_sub(8)?;
+  let contents = inflate(bytes.get(offset..trailer)?)?;
+  if checksum::crc32(&contents) != read_u32(bytes, trailer)? {
+    return None;
+  }
+  Some(Entry { name, bytes: contents })
+}
+
+/// None if it is damaged or would unpack to more than MAX_SIZE.
+fn inflate(data: &[u8]) -> Option<Vec<u8>> {
+  miniz_oxide::inflate::decompress_to_vec_with_limit(data, MAX_SIZE).ok()
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> Option<u16> {
+  Some(u16::from_le_bytes(bytes.get(offset..offset + 2)?.try_into().ok()?))
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Option<u32> {
+  Some(u32::from_le_bytes(bytes.get(offset..offset + 4)?.try_into().ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// A ZIP with one file, deflated if asked.
+  fn zip(name: &str, contents: &[u8], deflate: bool) -> Vec<u8> {
+    let data = if deflate { miniz_oxide::deflate::compress_to_vec(contents, 6) } else { contents.to_vec() };
+    let method: u16 = if deflate { 8 } else { 0 };
+    let crc = checksum::crc32(contents);
+    let mut bytes = vec![];
+    bytes.extend_from_slice(&0x04034B50u32.to_le_bytes());
+    bytes.extend_from_slice(&[20, 0, 0, 0]);
+    bytes.extend_from_slice(&method.to_le_bytes());
+    bytes.extend_from_slice(&[0; 4]);
+    bytes.extend_from_slice(&crc.to_le_bytes());
+    bytes.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(&(contents.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(&(name.len() as u16).to_le_bytes());
+    bytes.extend_from_slice(&[0; 2]);
+    bytes.extend_from_slice(name.as_bytes());
+    bytes.extend_from_slice(&data);
+
+    let directory_offset = bytes.len();
+    bytes.extend_from_slice(&0x02014B50u32.to_le_bytes());
+    bytes.extend_from_slice(&[20, 0, 20, 0, 0, 0]);
+    bytes.extend_from_slice(&method.to_le_bytes());
+    bytes.extend_from_slice(&[0; 4]);
+    bytes.extend_from_slice(&crc.to_le_bytes());
+    bytes.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(&(contents.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(&(name.len() as u16).to_le_bytes());
+    bytes.extend_from_slice(&[0; 12]);
+    bytes.extend_from_slice(&0u32.to_le_bytes());
+    bytes.extend_from_slice(name.as_bytes());
+    let directory_size = bytes.len() - directory_offset;
+
+    bytes.extend_from_slice(&0x06054B50u32.to_le_bytes());
+    bytes.extend_from_slice(&[0; 4]);
+    bytes.extend_from_slice(&[1, 0, 1, 0]);
+    bytes.extend_from_slice(&(directory_size as u32).to_le_bytes());
+    bytes.extend_from_slice(&(directory_offset as u32).to_le_bytes());
+    bytes.extend_from_slice(&[0; 2]);
+    bytes
+  }
+
+  fn gzip(name: &str, contents: &[u8]) -> Vec<u8> {
+    let mut bytes = vec![0x1F, 0x8B, 8, 0x08, 0, 0, 0, 0, 0, 0xFF];
+    bytes.extend_from_slice(name.as_bytes());
+    bytes.push(0);
+    bytes.extend_from_slice(&miniz_oxide::deflate::compress_to_vec(contents, 6));
+    bytes.extend_from_slice(&checksum::crc32(contents).to_le_bytes());
+    bytes.extend_from_slice(&(contents.len() as u32).to_le_bytes());
+    bytes
+  }
+
+  #[test]
+  fn not_an_archive() {
+    assert!(unpack(&[0x55, 0x08, 0x00]).is_none());
+  }
+
+  #[test]
+  fn stored_and_deflated_zip() {
+    let contents: Vec<u8> = (0..2048).map(|index| (index % 7) as u8).collect();
+    for deflate in [false, true] {
+      let entries = unpack(&zip("game.bin", &contents, deflate)).unwrap();
+      assert_eq!(entries.len(), 1);
+      assert_eq!(entries[0].name, "game.bin");
+      assert_eq!(entries[0].bytes, contents);
+    }
+  }
+
+  #[test]
+  fn damaged_zip_entry_is_skipped() {
+    let mut bytes = zip("game.bin", b"Videocart", false);
+    bytes[30 + "game.bin".len()] ^= 0xFF;
+    assert_eq!(unpack(&bytes).unwrap().len(), 0);
+  }
+
+  #[test]
+  fn gzip_keeps_its_name() {
+    let entries = unpack(&gzip("game.chf", b"CHANNEL F")).unwrap();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].name, "game.chf");
+    assert_eq!(entries[0].bytes, b"CHANNEL F");
+  }
+
+  #[test]
+  fn zip_bomb_is_refused() {
+    let contents = vec![0; MAX_SIZE + 1];
+    assert_eq!(unpack(&zip("bomb.bin", &contents, true)).unwrap().len(), 0);
+    assert!(unpack(&gzip("bomb.bin", &contents)).unwrap().is_empty());
+  }
+}
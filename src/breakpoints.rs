@@ -0,0 +1,261 @@
+use chips::fairchild_f8;
+
+// Breakpoints stop the program when PC0 reaches an address, before that instruction runs.
+// A breakpoint can have a condition, like "A == 0x3F" or "R10 >= 5 && Z == 1", which must hold for it to count as hit.
+// It can also wait for a number of hits before stopping, for code which runs many times before the interesting one.
+//
+// Operands: A, W, ISAR (or IS), DC0, DC1, PC1, R0 - R63 (scratchpad, decimal), and the flags S, C, Z, O and ICB.
+// Numbers are decimal, or hex with 0x or $ in front.
+
+pub(super) struct Breakpoint {
+  pub address: u16,
+  pub condition: Option<Condition>,
+  pub condition_text: String,
+  pub hits: u32,
+  pub stop_after: u32,  //Stop on every hit which is a multiple of this. 1 stops every time.
+}
+
+impl Breakpoint {
+  pub fn new(address: u16, condition_text: &str, stop_after: u32) -> Result<Self, String> {
+    let condition_text = condition_text.trim();
+    let condition = if condition_text.is_empty() { None } else { Some(Condition::parse(condition_text)?) };
+    Ok(Self {
+      address,
+      condition,
+      condition_text: condition_text.to_string(),
+      hits: 0,
+      stop_after: stop_after.max(1),
+    })
+  }
+
+  /// Called when PC0 is at the address. Returns true if we should stop.
+  pub fn hit(&mut self, board: &fairchild_f8::Board) -> bool {
+    if let Some(condition) = &self.condition {
+      if !condition.check(board) {
+        return false;
+      }
+    }
+    self.hits += 1;
+    self.hits.is_multiple_of(self.stop_after)
+  }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub(super) enum Operand {
+  A,
+  W,
+  Isar,
+  Dc0,
+  Dc1,
+  Pc1,
+  Register(u8),
+  Flag(u8), //The bit in W
+}
+
+impl Operand {
+  fn parse(name: &str) -> Option<Self> {
+    let name = name.to_uppercase();
+    match name.as_str() {
+      "A" => Some(Operand::A),
+      "W" => Some(Operand::W),
+      "IS" | "ISAR" => Some(Operand::Isar),
+      "DC0" | "DC" => Some(Operand::Dc0),
+      "DC1" => Some(Operand::Dc1),
+      "PC1" => Some(Operand::Pc1),
+      "S" => Some(Operand::Flag(0b00001)),
+      "C" => Some(Operand::Flag(0b00010)),
+      "Z" => Some(Operand::Flag(0b00100)),
+      "O" => Some(Operand::Flag(0b01000)),
+      "ICB" => Some(Operand::Flag(0b10000)),
+      _ => {
+        let register: u8 = name.strip_prefix('R')?.parse().ok()?;
+        if register < 64 { Some(Operand::Register(register)) } else { None }
+      },
+    }
+  }
+
+  pub fn value(&self, board: &fairchild_f8::Board) -> u16 {
+    match self {
+      Operand::A => board.cpu.a as u16,
+      Operand::W => board.cpu.w as u16,
+      Operand::Isar => (board.cpu.isar & 0x3F) as u16,
+      Operand::Dc0 => board.roms[0].dc0,
+      Operand::Dc1 => board.roms[0].dc1,
+      Operand::Pc1 => board.roms[0].pc1,
+      Operand::Register(register) => board.cpu.regs[*register as usize] as u16,
+      Operand::Flag(bit) => (board.cpu.w & bit != 0) as u16,
+    }
+  }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub(super) enum Comparison {
+  Equal,
+  NotEqual,
+  Less,
+  LessOrEqual,
+  Greater,
+  GreaterOrEqual,
+}
+
+impl Comparison {
+  fn parse(text: &str) -> Option<Self> {
+    match text {
+      "==" | "=" => Some(Comparison::Equal),
+      "!=" => Some(Comparison::NotEqual),
+      "<" => Some(Comparison::Less),
+      "<=" => Some(Comparison::LessOrEqual),
+      ">" => Some(Comparison::Greater),
+      ">=" => Some(Comparison::GreaterOrEqual),
+      _ => None,
+    }
+  }
+
+  pub fn check(&self, left: u16, right: u16) -> bool {
+    match self {
+      Comparison::Equal => left == right,
+      Comparison::NotEqual => left != right,
+      Comparison::Less => left < right,
+      Comparison::LessOrEqual => left <= right,
+      Comparison::Greater => left > right,
+      Comparison::GreaterOrEqual => left >= right,
+    }
+  }
+}
+
+/// Comparisons which must all hold.
+pub(super) struct Condition {
+  terms: Vec<(Operand, Comparison, u16)>,
+}
+
+impl Condition {
+  pub fn parse(text: &str) -> Result<Self, String> {
+    let mut terms = vec![];
+    for term in text.split("&&") {
+      let tokens = tokenize(term);
+      match tokens.as_slice() {
+        [operand, comparison, value] => {
+          let operand = Operand::parse(operand).ok_or_else(|| format!("Unknown operand \"{}\"", operand))?;
+          let comparison = Comparison::parse(comparison).ok_or_else(|| format!("Unknown comparison \"{}\"", comparison))?;
+          let value = parse_number(value).ok_or_else(|| format!("\"{}\" is not a number", value))?;
+          terms.push((operand, comparison, value));
+        },
+        _ => return Err(format!("\"{}\" should look like A == 0x3F", term.trim())),
+      }
+    }
+    Ok(Self { terms })
+  }
+
+  pub fn check(&self, board: &fairchild_f8::Board) -> bool {
+    self.terms.iter().all(|(operand, comparison, value)| comparison.check(operand.value(board), *value))
+  }
+}
+
+/// Splits "A==0x3F" into "A", "==", "0x3F". Spaces are optional.
+fn tokenize(text: &str) -> Vec<String> {
+  let mut tokens: Vec<String> = vec![];
+  let mut last_is_symbol = None;
+  for character in text.chars() {
+    if character.is_whitespace() {
+      last_is_symbol = None;
+      continue;
+    }
+    let is_symbol = "=!<>".contains(character);
+    match tokens.last_mut() {
+      Some(token) if last_is_symbol == Some(is_symbol) => token.push(character),
+      _ => tokens.push(character.to_string()),
+    }
+    last_is_symbol = Some(is_symbol);
+  }
+  tokens
+}
+
+/// Decimal, or hex starting with 0x or $.
+pub(super) fn parse_number(text: &str) -> Option<u16> {
+  let text = text.trim();
+  if let Some(hex) = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")).or_else(|| text.strip_prefix('$')) {
+    u16::from_str_radix(hex, 16).ok()
+  } else {
+    text.parse().ok()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn tokens_with_and_without_spaces() {
+    assert_eq!(tokenize("A==0x3F"), ["A", "==", "0x3F"]);
+    assert_eq!(tokenize("  R10 >= 5 "), ["R10", ">=", "5"]);
+    assert_eq!(tokenize("DC0!=$2800"), ["DC0", "!=", "$2800"]);
+    assert_eq!(tokenize("A = = 1"), ["A", "=", "=", "1"]);
+  }
+
+  #[test]
+  fn numbers() {
+    assert_eq!(parse_number("63"), Some(63));
+    assert_eq!(parse_number("0x3F"), Some(0x3F));
+    assert_eq!(parse_number("0X3f"), Some(0x3F));
+    assert_eq!(parse_number("$2800"), Some(0x2800));
+    assert_eq!(parse_number("65535"), Some(0xFFFF));
+    assert_eq!(parse_number("65536"), None);
+    assert_eq!(parse_number("3F"), None);
+    assert_eq!(parse_number("0x"), None);
+    assert_eq!(parse_number("-1"), None);
+  }
+
+  #[test]
+  fn valid_conditions() {
+    let condition = Condition::parse("A == 0x3F").unwrap();
+    assert!(condition.terms == vec![(Operand::A, Comparison::Equal, 0x3F)]);
+    let condition = Condition::parse("r10>=5 && Z==1&&is<$10 && dc1 != 0 && PC1 > 2 && w <= 3").unwrap();
+    assert!(condition.terms == vec![
+      (Operand::Register(10), Comparison::GreaterOrEqual, 5),
+      (Operand::Flag(4), Comparison::Equal, 1),
+      (Operand::Isar, Comparison::Less, 0x10),
+      (Operand::Dc1, Comparison::NotEqual, 0),
+      (Operand::Pc1, Comparison::Greater, 2),
+      (Operand::W, Comparison::LessOrEqual, 3),
+    ]);
+    assert!(Condition::parse("DC = 1").unwrap().terms == vec![(Operand::Dc0, Comparison::Equal, 1)]);
+    assert!(Condition::parse("ISAR=1").unwrap().terms == vec![(Operand::Isar, Comparison::Equal, 1)]);
+  }
+
+  #[test]
+  fn flags_are_their_bits_in_w() {
+    for (name, bit) in [("S", 1), ("C", 2), ("Z", 4), ("O", 8), ("ICB", 16)] {
+      assert!(Operand::parse(name) == Some(Operand::Flag(bit)), "{}", name);
+      assert!(Operand::parse(&name.to_lowercase()) == Some(Operand::Flag(bit)), "{}", name);
+    }
+  }
+
+  #[test]
+  fn bad_conditions() {
+    assert_eq!(Condition::parse("B == 1").err().unwrap(), "Unknown operand \"B\"");
+    assert_eq!(Condition::parse("R64 == 1").err().unwrap(), "Unknown operand \"R64\"");
+    assert_eq!(Condition::parse("A => 1").err().unwrap(), "Unknown comparison \"=>\"");
+    assert_eq!(Condition::parse("A == x").err().unwrap(), "\"x\" is not a number");
+    assert_eq!(Condition::parse("A == 0x10000").err().unwrap(), "\"0x10000\" is not a number");
+    assert_eq!(Condition::parse("A").err().unwrap(), "\"A\" should look like A == 0x3F");
+    assert_eq!(Condition::parse("A == 1 && ").err().unwrap(), "\"\" should look like A == 0x3F");
+    assert_eq!(Condition::parse("A == 1 == 2").err().unwrap(), "\"A == 1 == 2\" should look like A == 0x3F");
+  }
+
+  #[test]
+  fn comparisons() {
+    assert!(Comparison::Equal.check(3, 3) && !Comparison::Equal.check(3, 4));
+    assert!(Comparison::NotEqual.check(3, 4) && !Comparison::NotEqual.check(3, 3));
+    assert!(Comparison::Less.check(3, 4) && !Comparison::Less.check(4, 4));
+    assert!(Comparison::LessOrEqual.check(4, 4) && !Comparison::LessOrEqual.check(5, 4));
+    assert!(Comparison::Greater.check(5, 4) && !Comparison::Greater.check(4, 4));
+    assert!(Comparison::GreaterOrEqual.check(4, 4) && !Comparison::GreaterOrEqual.check(3, 4));
+  }
+
+  #[test]
+  fn stop_after_is_at_least_one() {
+    assert_eq!(Breakpoint::new(0x802, "", 0).unwrap().stop_after, 1);
+    assert!(Breakpoint::new(0x802, "  ", 3).unwrap().condition.is_none());
+    assert!(Breakpoint::new(0x802, "Q == 1", 1).is_err());
+  }
+}
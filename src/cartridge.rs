@@ -0,0 +1,271 @@
+use chips::fairchild_f8;
+use super::rom_db::CartridgeType;
+
+// Cartridges can have more than ROM in them. The extra hardware talks to the CPU over I/O ports, or is RAM mapped into memory.
+// Like the keyboard, we look at the port latches after every instruction and answer on the input side of the ports.
+// Where writing the same value again matters, like the 3853 timer, we're also told which port the instruction wrote.
+//
+// The chips Board can't write memory or be interrupted from outside, so the rest is done with what it does have.
+// A multicart bank is switched by building a board with the new bank and moving the state of the old one into it.
+// The 3853 interrupts by moving the program counters itself, as the CPU does when it takes an interrupt.
+// There is no RAM to map into memory, so Schach runs as plain ROM, without its 2K of RAM.
+
+const SRAM_PORTS: [u8; 2] = [0x20, 0x24];  //Hangman answers on 0x20/0x21, Maze on 0x24/0x25. Address low bits are on the next port.
+const SMI_PORTS: u8 = 0x0C; //Interrupt vector high, interrupt vector low, interrupt control, timer.
+const SMI_TIMER_PRESCALE: u64 = 31; //The timer counts once every 31 clock ticks.
+const SMI_TIMER_INTERRUPTS: u8 = 0b11;  //Interrupt control bits: 00 and 10 are off, 01 is the external interrupt only.
+const ICB: u8 = 0b10000; //The interrupt control bit of W. The CPU only takes interrupts while it's set.
+const MULTICART_BANK_PORT: u8 = 0x18;
+pub(super) const MULTICART_BANK_SIZE: usize = 0x2000;
+
+pub(super) enum Cartridge {
+  Rom,
+  Sram2102(Sram2102),
+  Smi3853(Smi3853),
+  Multicart(Multicart),
+}
+
+impl Cartridge {
+  /// The BIOS is needed by a multicart, to build the board again with another bank.
+  pub fn new(cartridge_type: CartridgeType, bios: &[u8], image: Option<&[u8]>) -> Self {
+    match cartridge_type {
+      CartridgeType::Rom => Cartridge::Rom,
+      CartridgeType::Sram2102 => Cartridge::Sram2102(Sram2102 { bits: vec![false; 1024], latches: [[0; 2]; 2] }),
+      CartridgeType::Smi3853 => Cartridge::Smi3853(Smi3853 { vector: 0, control: 0, timer: 0, timer_latch: 0, ticks: 0, pending: false }),
+      CartridgeType::Schach => {
+        log::warn!(target: "loader", "The 2K of RAM of the Schach cartridge isn't emulated. It runs as plain ROM.");
+        Cartridge::Rom
+      },
+      CartridgeType::Multicart => Cartridge::Multicart(Multicart { bios: bios.to_vec(), image: image.unwrap_or(&[]).to_vec(), bank: 0 }),
+    }
+  }
+
+  /// What the board gets as its cartridge ROM. A multicart only shows one bank at a time.
+  pub fn board_image(&self, image: Option<&[u8]>) -> Option<Vec<u8>> {
+    match self {
+      Cartridge::Multicart(multicart) => Some(multicart.bank_bytes(0).to_vec()),
+      _ => image.map(|image| image.to_vec()),
+    }
+  }
+
+  /// The RAM a battery would keep, if this cartridge has any. The 2102 bits are packed 8 to a byte.
+  pub fn save_ram(&self, _board: &fairchild_f8::Board) -> Option<Vec<u8>> {
+    match self {
+      Cartridge::Sram2102(sram) => Some(sram.bits.chunks(8).map(|bits| {
+        bits.iter().enumerate().fold(0, |byte, (index, &bit)| byte | ((bit as u8) << index))
+      }).collect()),
+      _ => None,
+    }
+  }
+
+  pub fn load_ram(&mut self, _board: &mut fairchild_f8::Board, bytes: &[u8]) {
+    if let Cartridge::Sram2102(sram) = self {
+      for (index, bit) in sram.bits.iter_mut().enumerate() {
+        *bit = bytes.get(index / 8).is_some_and(|byte| byte & (1 << (index % 8)) != 0);
+      }
+    }
+  }
+
+  /// port_write is the port the instruction wrote, decoded before it ran.
+  pub fn run_cycle(&mut self, board: &mut fairchild_f8::Board, clock_ticks: usize, port_write: Option<u8>) {
+    match self {
+      Cartridge::Rom => (),
+      Cartridge::Sram2102(sram) => sram.run_cycle(board),
+      Cartridge::Smi3853(smi) => smi.run_cycle(board, clock_ticks, port_write),
+      Cartridge::Multicart(multicart) => multicart.run_cycle(board),
+    }
+  }
+}
+
+/// 1024 x 1 bit of static RAM.
+/// First port: bit 0 = write, bit 1 = A9, bit 2 = A8, bit 3 = data in, bit 7 = data out. Second port: A0 - A7.
+pub(super) struct Sram2102 {
+  bits: Vec<bool>,  //1024 of them
+  latches: [[u8; 2]; 2], //What was last on each pair of ports.
+}
+
+impl Sram2102 {
+  fn run_cycle(&mut self, board: &mut fairchild_f8::Board) {
+    for (pair, port) in SRAM_PORTS.into_iter().enumerate() {
+      if let Some(answer) = self.access(pair, board.read_port(port), board.read_port(port + 1)) {
+        board.ports[port as usize] = answer[0];
+        board.ports[port as usize + 1] = answer[1];
+      }
+    }
+  }
+
+  /// What the pair of ports answers after the CPU left these values on them. None if nothing changed.
+  fn access(&mut self, pair: usize, control: u8, address_low: u8) -> Option<[u8; 2]> {
+    if [control, address_low] == self.latches[pair] && control & 0b1 == 0 {
+      return None;
+    }
+    self.latches[pair] = [control, address_low];
+    let address = address_low as usize | ((control as usize & 0b100) << 6) | ((control as usize & 0b10) << 8);
+    //The write line is a level, not an edge. As long as it's held, the data is written.
+    if control & 0b1 != 0 {
+      self.bits[address] = control & 0b1000 != 0;
+    }
+    Some([(control & 0b01111111) | if self.bits[address] { 0b10000000 } else { 0 }, address_low])
+  }
+}
+
+/// The 3853 Static Memory Interface. Only its programmable timer and interrupt logic matter to cartridges.
+/// The real timer is a polynomial shift counter. Here it simply counts down, which takes the same time.
+pub(super) struct Smi3853 {
+  vector: u16,
+  control: u8,
+  timer: u8,
+  timer_latch: u8,  //The value last written to the timer port, which it starts from again after an interrupt.
+  ticks: u64,
+  pending: bool,  //The timer ran out, and the CPU hasn't taken the interrupt yet.
+}
+
+impl Smi3853 {
+  fn run_cycle(&mut self, board: &mut fairchild_f8::Board, clock_ticks: usize, port_write: Option<u8>) {
+    self.vector = (board.read_port(SMI_PORTS) as u16) << 8 | board.read_port(SMI_PORTS + 1) as u16;
+    self.control = board.read_port(SMI_PORTS + 2);
+    let timer_write = if port_write == Some(SMI_PORTS + 3) { Some(board.read_port(SMI_PORTS + 3)) } else { None };
+    self.count(clock_ticks, timer_write);
+
+    //The CPU decides if it takes the interrupt, depending on its interrupt control bit. Taking it clears the bit.
+    if self.pending && board.cpu.w & ICB != 0 {
+      self.pending = false;
+      for rom in board.roms.iter_mut() {
+        rom.pc1 = rom.pc0;
+        rom.pc0 = self.vector;
+      }
+      board.cpu.w &= !ICB;
+    }
+  }
+
+  /// Runs the timer for the clock ticks of one instruction. timer_write is the value, if the instruction wrote the timer.
+  fn count(&mut self, clock_ticks: usize, timer_write: Option<u8>) {
+    //Every write starts the timer over, even with the value it already had.
+    if let Some(value) = timer_write {
+      self.timer_latch = value;
+      self.timer = value;
+      self.ticks = 0;
+    }
+
+    if self.timer == 0 {
+      return;
+    }
+    self.ticks += clock_ticks as u64;
+    while self.ticks >= SMI_TIMER_PRESCALE && self.timer > 0 {
+      self.ticks -= SMI_TIMER_PRESCALE;
+      self.timer -= 1;
+      if self.timer == 0 && self.control & SMI_TIMER_INTERRUPTS == SMI_TIMER_INTERRUPTS {
+        self.pending = true;
+        self.timer = self.timer_latch;
+      }
+    }
+  }
+}
+
+/// A flash cartridge with many games. Writing to the bank port swaps which part of the flash is seen at 0x0800.
+pub(super) struct Multicart {
+  bios: Vec<u8>,
+  image: Vec<u8>,
+  bank: u8,
+}
+
+impl Multicart {
+  fn bank_bytes(&self, bank: u8) -> &[u8] {
+    let start = (bank as usize * MULTICART_BANK_SIZE).min(self.image.len());
+    let end = (start + MULTICART_BANK_SIZE).min(self.image.len());
+    &self.image[start..end]
+  }
+
+  fn run_cycle(&mut self, board: &mut fairchild_f8::Board) {
+    let bank = board.read_port(MULTICART_BANK_PORT);
+    if bank != self.bank && (bank as usize) * MULTICART_BANK_SIZE < self.image.len() {
+      self.bank = bank;
+      let new_board = fairchild_f8::Board::new(Some(self.bios.clone()), Some(self.bank_bytes(bank).to_vec()));
+      carry_over(board, new_board);
+    }
+  }
+}
+
+/// Replaces the board with one which has other ROMs, but keeps the CPU, scratchpad, VRAM and ports of the running one.
+pub(super) fn carry_over(board: &mut fairchild_f8::Board, mut new_board: fairchild_f8::Board) {
+  std::mem::swap(&mut new_board.cpu, &mut board.cpu);
+  std::mem::swap(&mut new_board.vram, &mut board.vram);
+  std::mem::swap(&mut new_board.ports, &mut board.ports);
+  //Each memory chip keeps its own copy of the program counters and data counter, which the CPU keeps in step.
+  let running = &board.roms[0];
+  let (pc0, pc1, dc0, dc1) = (running.pc0, running.pc1, running.dc0, running.dc1);
+  for rom in new_board.roms.iter_mut() {
+    rom.pc0 = pc0;
+    rom.pc1 = pc1;
+    rom.dc0 = dc0;
+    rom.dc1 = dc1;
+  }
+  *board = new_board;
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn sram() -> Sram2102 {
+    Sram2102 { bits: vec![false; 1024], latches: [[0; 2]; 2] }
+  }
+
+  fn smi(control: u8) -> Smi3853 {
+    Smi3853 { vector: 0, control, timer: 0, timer_latch: 0, ticks: 0, pending: false }
+  }
+
+  #[test]
+  fn sram_bit_written_then_read() {
+    let mut sram = sram();
+    //Write a 1 to address 0x3A5: A9 and A8 on the first port, the low bits on the second.
+    assert_eq!(sram.access(0, 0b1111, 0xA5), Some([0b10001111, 0xA5]));
+    //The write line is let go. The bit is still there.
+    assert_eq!(sram.access(0, 0b0110, 0xA5), Some([0b10000110, 0xA5]));
+    assert_eq!(sram.access(0, 0b0110, 0xA5), None);
+    //Another address reads 0, and the pair on 0x24 doesn't share latches with this one.
+    assert_eq!(sram.access(0, 0b0110, 0xA4), Some([0b00000110, 0xA4]));
+    assert_eq!(sram.access(1, 0b0110, 0xA5), Some([0b10000110, 0xA5]));
+  }
+
+  #[test]
+  fn sram_write_is_a_level() {
+    let mut sram = sram();
+    sram.access(0, 0b1001, 0x10);
+    //Same latches, but the write line is still held, so the new data goes in.
+    assert_eq!(sram.access(0, 0b1001, 0x10), Some([0b10001001, 0x10]));
+    assert_eq!(sram.access(0, 0b0001, 0x10), Some([0b00000001, 0x10]));
+  }
+
+  #[test]
+  fn timer_runs_out_and_starts_over() {
+    let mut smi = smi(SMI_TIMER_INTERRUPTS);
+    smi.count(0, Some(2));
+    smi.count(SMI_TIMER_PRESCALE as usize, None);
+    assert!(!smi.pending);
+    smi.count(SMI_TIMER_PRESCALE as usize, None);
+    assert!(smi.pending);
+    assert_eq!(smi.timer, 2);
+  }
+
+  #[test]
+  fn timer_interrupt_needs_both_control_bits() {
+    for control in [0b00, 0b01, 0b10] {
+      let mut smi = smi(control);
+      smi.count(0, Some(1));
+      smi.count(SMI_TIMER_PRESCALE as usize * 4, None);
+      assert!(!smi.pending);
+      assert_eq!(smi.timer, 0);
+    }
+  }
+
+  #[test]
+  fn timer_write_starts_over() {
+    let mut smi = smi(SMI_TIMER_INTERRUPTS);
+    smi.count(0, Some(3));
+    smi.count(SMI_TIMER_PRESCALE as usize * 2 + 5, None);
+    assert_eq!(smi.timer, 1);
+    smi.count(0, Some(3));
+    assert_eq!((smi.timer, smi.ticks), (3, 0));
+  }
+}
@@ -0,0 +1,369 @@
+use chips::fairchild_f8;
+use super::cartridge;
+use super::checksum;
+use super::html;
+use super::loader;
+use super::rom_db;
+use super::save_ram;
+
+// Cheats freeze bytes: every frame, they are written back to the value of the code.
+//
+// A code is one or more writes separated by commas:
+//   R10=09     Scratchpad register 10 (decimal, like in breakpoints).
+//   CR1F=00    Cartridge RAM as it is saved, for RAM behind I/O ports. The 2102 bits are packed 8 to a byte.
+// Values are hex. Memory isn't offered, since the chips Board has no way to write it from outside.
+//
+// The search finds where a game keeps something, like the lives: take a snapshot, play until the lives go down,
+// then keep only the bytes which decreased. Each comparison is against the snapshot before it.
+//
+// Cheats belong to a game. They are kept in localStorage under the SHA-1 of the cartridge ROM, like the save RAM,
+// and the list and the search start over whenever another cartridge goes in or the console is turned off and on.
+// The cheats the ROM database knows for the game are shown above them, each a button to add it.
+// The search box narrows both lists to the cheats whose names have every word typed.
+
+const MAX_RESULTS: usize = 50;  //Search results shown. There can be far more at first.
+
+#[derive(Clone, Copy, PartialEq)]
+enum Location {
+  Scratchpad(u8),
+  CartridgeRam(usize),
+}
+
+impl Location {
+  fn parse(text: &str) -> Option<Self> {
+    let text = text.trim().to_uppercase();
+    if let Some(offset) = text.strip_prefix("CR") {
+      return usize::from_str_radix(offset, 16).ok().map(Location::CartridgeRam);
+    }
+    let register = text.strip_prefix('R')?;
+    register.parse().ok().filter(|register| *register < 64).map(Location::Scratchpad)
+  }
+
+  fn describe(&self) -> String {
+    match self {
+      Location::Scratchpad(register) => format!("R{}", register),
+      Location::CartridgeRam(offset) => format!("CR{:X}", offset),
+    }
+  }
+}
+
+struct Cheat {
+  name: String,
+  code: String,
+  writes: Vec<(Location, u8)>,
+  enabled: bool,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub(super) enum SearchSpace {
+  Scratchpad,
+  CartridgeRam,
+}
+
+impl SearchSpace {
+  pub fn from_name(name: &str) -> Option<Self> {
+    match name {
+      "scratchpad" => Some(SearchSpace::Scratchpad),
+      "cart_ram" => Some(SearchSpace::CartridgeRam),
+      _ => None,
+    }
+  }
+
+  fn location(&self, address: usize) -> Location {
+    match self {
+      SearchSpace::Scratchpad => Location::Scratchpad(address as u8),
+      SearchSpace::CartridgeRam => Location::CartridgeRam(address),
+    }
+  }
+
+  fn snapshot(&self, board: &fairchild_f8::Board, cartridge: &cartridge::Cartridge) -> Vec<u8> {
+    match self {
+      SearchSpace::Scratchpad => board.cpu.regs.to_vec(),
+      SearchSpace::CartridgeRam => cartridge.save_ram(board).unwrap_or_default(),
+    }
+  }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub(super) enum Comparison {
+  Equal,
+  Changed,
+  Increased,
+  Decreased,
+}
+
+impl Comparison {
+  pub fn from_name(name: &str) -> Option<Self> {
+    match name {
+      "equal" => Some(Comparison::Equal),
+      "changed" => Some(Comparison::Changed),
+      "increased" => Some(Comparison::Increased),
+      "decreased" => Some(Comparison::Decreased),
+      _ => None,
+    }
+  }
+
+  fn check(&self, before: u8, now: u8) -> bool {
+    match self {
+      Comparison::Equal => now == before,
+      Comparison::Changed => now != before,
+      Comparison::Increased => now > before,
+      Comparison::Decreased => now < before,
+    }
+  }
+}
+
+struct Search {
+  space: SearchSpace,
+  snapshot: Vec<u8>,
+  candidates: Vec<usize>,
+}
+
+pub(super) struct Cheats {
+  key: Option<String>,  //None without a cartridge. The cheats are then forgotten with the BIOS games.
+  cheats: Vec<Cheat>,
+  known: &'static [(&'static str, &'static str)],  //From the ROM database.
+  filter: String,
+  search: Option<Search>,
+  cheat_db: Option<web_sys::Element>,
+  cheat_db_html: String,
+  cheat_list: Option<web_sys::Element>,
+  cheat_html: String,
+  cheat_error: Option<web_sys::Element>,
+  search_results: Option<web_sys::Element>,
+  search_html: String,
+}
+
+impl Cheats {
+  pub fn new(roms: &loader::Roms) -> Self {
+    let document = web_sys::window().unwrap().document().unwrap();
+    let mut cheats = Self {
+      key: None,
+      cheats: vec![],
+      known: &[],
+      filter: String::new(),
+      search: None,
+      cheat_db: document.get_element_by_id("cheat_db"),
+      cheat_db_html: String::new(),
+      cheat_list: document.get_element_by_id("cheat_list"),
+      cheat_html: String::new(),
+      cheat_error: document.get_element_by_id("cheat_error"),
+      search_results: document.get_element_by_id("cheat_search_results"),
+      search_html: String::new(),
+    };
+    cheats.load(roms);
+    cheats
+  }
+
+  /// Swaps in the cheats kept for the cartridge, and drops the search. Called for a new cartridge and on power on.
+  pub fn load(&mut self, roms: &loader::Roms) {
+    self.key = roms.cartridge.as_ref().map(|image| format!("fairchild_ves.cheats.{}", checksum::to_hex(&checksum::sha1(image))));
+    self.search = None;
+    let stored = self.key.as_deref().and_then(|key| save_ram::local_storage()?.get_item(key).ok()?).unwrap_or_default();
+    self.cheats = stored.lines().filter_map(from_line).collect();
+    self.known = rom_db::cartridge_info(roms).map_or(&[], |info| info.cheats);
+  }
+
+  /// Only cheats with every word of the filter in their name are listed.
+  pub fn set_filter(&mut self, filter: &str) {
+    self.filter = filter.to_string();
+  }
+
+  /// Shows what was wrong with the code, if anything.
+  pub fn add(&mut self, name: &str, code: &str) {
+    let error = match parse_code(code) {
+      Ok(writes) if writes.is_empty() => "The code is empty".to_string(),
+      Ok(writes) => {
+        let name = if name.trim().is_empty() { code.trim() } else { name.trim() };
+        self.cheats.push(Cheat { name: name.to_string(), code: code.trim().to_string(), writes, enabled: true });
+        self.store();
+        String::new()
+      },
+      Err(error) => error,
+    };
+    if let Some(element) = &self.cheat_error {
+      element.set_text_content(Some(&error));
+    }
+  }
+
+  pub fn toggle(&mut self, index: usize) {
+    if let Some(cheat) = self.cheats.get_mut(index) {
+      cheat.enabled = !cheat.enabled;
+      self.store();
+    }
+  }
+
+  pub fn remove(&mut self, index: usize) {
+    if index < self.cheats.len() {
+      self.cheats.remove(index);
+      self.store();
+    }
+  }
+
+  fn store(&self) {
+    if let (Some(key), Some(storage)) = (&self.key, save_ram::local_storage()) {
+      let lines: Vec<String> = self.cheats.iter().map(to_line).collect();
+      let _ = storage.set_item(key, &lines.join("\n"));
+    }
+  }
+
+  /// Writes the frozen values. Called once per frame.
+  pub fn run_refresh_cycle(&self, board: &mut fairchild_f8::Board, cartridge: &mut cartridge::Cartridge) {
+    let mut ram = None;
+    for &(location, value) in self.cheats.iter().filter(|cheat| cheat.enabled).flat_map(|cheat| cheat.writes.iter()) {
+      match location {
+        Location::Scratchpad(register) => board.cpu.regs[register as usize] = value,
+        Location::CartridgeRam(offset) => {
+          let ram = ram.get_or_insert_with(|| cartridge.save_ram(board).unwrap_or_default());
+          if let Some(byte) = ram.get_mut(offset) {
+            *byte = value;
+          }
+        },
+      }
+    }
+    if let Some(ram) = ram {
+      cartridge.load_ram(board, &ram);
+    }
+  }
+
+  /// Every byte is a candidate again.
+  pub fn start_search(&mut self, space: SearchSpace, board: &fairchild_f8::Board, cartridge: &cartridge::Cartridge) {
+    let snapshot = space.snapshot(board, cartridge);
+    let candidates = (0..snapshot.len()).collect();
+    self.search = Some(Search { space, snapshot, candidates });
+  }
+
+  /// Keeps the candidates which compare to the last snapshot as asked, and takes a new snapshot.
+  pub fn search(&mut self, comparison: Comparison, board: &fairchild_f8::Board, cartridge: &cartridge::Cartridge) {
+    if let Some(search) = &mut self.search {
+      let snapshot = search.space.snapshot(board, cartridge);
+      let last = &search.snapshot;
+      search.candidates.retain(|&address| {
+        matches!((last.get(address), snapshot.get(address)), (Some(&before), Some(&now)) if comparison.check(before, now))
+      });
+      search.snapshot = snapshot;
+    }
+  }
+
+  pub fn print(&mut self, board: &fairchild_f8::Board, cartridge: &cartridge::Cartridge) {
+    let known: Vec<_> = self.known.iter().filter(|(name, _)| matches(name, &self.filter)).collect();
+    let html = match (&self.key, self.known, known.as_slice()) {
+      (None, _, _) => String::new(),
+      (Some(_), [], _) => "No cheats known for this game".to_string(),
+      (Some(_), _, []) => "No known cheats match the search".to_string(),
+      (Some(_), _, known) => known.iter().map(|(name, code)| {
+        format!("<button onclick=\"pendingCommands.push(['cheat_add', '{}', '{}'])\">{}</button>", html::escape(&name.replace('\'', "\\'")), code, html::escape(name))
+      }).collect::<Vec<String>>().join(" "),
+    };
+    if self.cheat_db_html != html {
+      if let Some(element) = &self.cheat_db {
+        element.set_inner_html(&html);
+      }
+      self.cheat_db_html = html;
+    }
+
+    let mut html = String::new();
+    for (index, cheat) in self.cheats.iter().enumerate().filter(|(_, cheat)| matches(&cheat.name, &self.filter)) {
+      html.push_str(&format!(
+        "<tr><td><input type=\"checkbox\"{} onchange=\"pendingCommands.push(['cheat_toggle', {}])\"></td><td>{}</td><td>{}</td><td><button onclick=\"pendingCommands.push(['cheat_remove', {}])\">X</button></td></tr>",
+        if cheat.enabled { " checked" } else { "" }, index, html::escape(&cheat.name), html::escape(&cheat.code), index,
+      ));
+    }
+    if self.cheat_html != html {
+      if let Some(element) = &self.cheat_list {
+        element.set_inner_html(&html);
+      }
+      self.cheat_html = html;
+    }
+
+    let mut html = String::new();
+    if let Some(search) = &self.search {
+      let now = search.space.snapshot(board, cartridge);
+      html.push_str(&format!("<tr><td colspan=\"3\">{} candidates</td></tr>", search.candidates.len()));
+      for &address in search.candidates.iter().take(MAX_RESULTS) {
+        let location = search.space.location(address).describe();
+        let value = now.get(address).copied().unwrap_or(0);
+        html.push_str(&format!(
+          "<tr><td>{}</td><td>{:02X}</td><td><button onclick=\"pendingCommands.push(['cheat_add', '', '{}={:02X}'])\">Freeze</button></td></tr>",
+          location, value, location, value,
+        ));
+      }
+    }
+    if self.search_html != html {
+      if let Some(element) = &self.search_results {
+        element.set_inner_html(&html);
+      }
+      self.search_html = html;
+    }
+  }
+}
+
+/// Whether the name has every word of the filter, ignoring case.
+fn matches(name: &str, filter: &str) -> bool {
+  let name = name.to_lowercase();
+  filter.to_lowercase().split_whitespace().all(|word| name.contains(word))
+}
+
+fn parse_code(code: &str) -> Result<Vec<(Location, u8)>, String> {
+  code.split(',').filter(|write| !write.trim().is_empty()).map(|write| {
+    let (location, value) = write.split_once('=').ok_or_else(|| format!("\"{}\" should look like R10=09", write.trim()))?;
+    let location = Location::parse(location).ok_or_else(|| format!("\"{}\" is not a register or address", location.trim()))?;
+    let value = u8::from_str_radix(value.trim(), 16).map_err(|_| format!("\"{}\" is not a hex byte", value.trim()))?;
+    Ok((location, value))
+  }).collect()
+}
+
+/// A stored cheat is one line: 1 or 0 for whether it is on, the code, then the name, separated by tabs.
+fn to_line(cheat: &Cheat) -> String {
+  let name = cheat.name.replace(['\t', '\n', '\r'], " ");
+  format!("{}\t{}\t{}", if cheat.enabled { 1 } else { 0 }, cheat.code, name)
+}
+
+fn from_line(line: &str) -> Option<Cheat> {
+  let mut fields = line.splitn(3, '\t');
+  let enabled = fields.next()? == "1";
+  let code = fields.next()?.to_string();
+  let name = fields.next()?.to_string();
+  let writes = parse_code(&code).ok().filter(|writes| !writes.is_empty())?;
+  Some(Cheat { name, code, writes, enabled })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn code_with_every_location() {
+    let writes = parse_code("R10=09, cr1f=0").unwrap();
+    assert!(writes == vec![(Location::Scratchpad(10), 0x09), (Location::CartridgeRam(0x1F), 0)]);
+  }
+
+  #[test]
+  fn filter_needs_every_word() {
+    assert!(matches("Infinite lives", ""));
+    assert!(matches("Infinite lives", "LIVES"));
+    assert!(matches("Infinite lives", " lives  inf "));
+    assert!(!matches("Infinite lives", "infinite time"));
+    assert!(!matches("", "lives"));
+  }
+
+  #[test]
+  fn bad_codes() {
+    assert_eq!(parse_code("R10").err().unwrap(), "\"R10\" should look like R10=09");
+    assert_eq!(parse_code("R64=00").err().unwrap(), "\"R64\" is not a register or address");
+    assert_eq!(parse_code("2A00=FF").err().unwrap(), "\"2A00\" is not a register or address");
+    assert_eq!(parse_code("R1=100").err().unwrap(), "\"100\" is not a hex byte");
+    assert!(parse_code(" , ").unwrap().is_empty());
+  }
+
+  #[test]
+  fn stored_cheats_come_back() {
+    let cheat = Cheat { name: "Lives\t9".to_string(), code: "R10=09".to_string(), writes: vec![(Location::Scratchpad(10), 9)], enabled: false };
+    let line = to_line(&cheat);
+    assert_eq!(line, "0\tR10=09\tLives 9");
+    let stored = from_line(&line).unwrap();
+    assert_eq!((stored.name.as_str(), stored.code.as_str(), stored.enabled), ("Lives 9", "R10=09", false));
+    assert!(stored.writes == cheat.writes);
+    assert!(from_line("1\tnonsense\tBroken").is_none());
+  }
+}
@@ -0,0 +1,82 @@
+/// CRC-32 as used by ZIP and gzip (reflected, polynomial 0xEDB88320).
+pub(super) fn crc32(bytes: &[u8]) -> u32 {
+  let mut crc = 0xFFFFFFFF_u32;
+  for &byte in bytes {
+    crc ^= byte as u32;
+    for _ in 0..8 {
+      crc = if crc & 1 == 1 { (crc >> 1) ^ 0xEDB88320 } else { crc >> 1 };
+    }
+  }
+  !crc
+}
+
+/// SHA-1, for telling apart ROMs where a CRC-32 isn't enough.
+pub(super) fn sha1(bytes: &[u8]) -> [u8; 20] {
+  let mut state: [u32; 5] = [0x67452301, 0xEFCDAB89, 0x98BADCFE, 0x10325476, 0xC3D2E1F0];
+
+  //Pad with a 1 bit, zeros, and the length in bits, to a multiple of 64 bytes.
+  let mut message = bytes.to_vec();
+  message.push(0x80);
+  while message.len() % 64 != 56 {
+    message.push(0);
+  }
+  message.extend_from_slice(&((bytes.len() as u64) * 8).to_be_bytes());
+
+  for block in message.chunks(64) {
+    let mut words = [0_u32; 80];
+    for (index, word) in block.chunks(4).enumerate() {
+      words[index] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+    }
+    for index in 16..80 {
+      words[index] = (words[index - 3] ^ words[index - 8] ^ words[index - 14] ^ words[index - 16]).rotate_left(1);
+    }
+
+    let [mut a, mut b, mut c, mut d, mut e] = state;
+    for (index, &word) in words.iter().enumerate() {
+      let (f, k) = match index {
+        0..=19 => ((b & c) | (!b & d), 0x5A827999),
+        20..=39 => (b ^ c ^ d, 0x6ED9EBA1),
+        40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1BBCDC),
+        _ => (b ^ c ^ d, 0xCA62C1D6),
+      };
+      let temp = a.rotate_left(5).wrapping_add(f).wrapping_add(e).wrapping_add(k).wrapping_add(word);
+      e = d;
+      d = c;
+      c = b.rotate_left(30);
+      b = a;
+      a = temp;
+    }
+    for (value, new_value) in state.iter_mut().zip([a, b, c, d, e]) {
+      *value = value.wrapping_add(new_value);
+    }
+  }
+
+  let mut digest = [0; 20];
+  for (index, value) in state.iter().enumerate() {
+    digest[index * 4..index * 4 + 4].copy_from_slice(&value.to_be_bytes());
+  }
+  digest
+}
+
+pub(super) fn to_hex(bytes: &[u8]) -> String {
+  bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn crc32_check_values() {
+    assert_eq!(crc32(b""), 0);
+    assert_eq!(crc32(b"123456789"), 0xCBF43926);
+  }
+
+  #[test]
+  fn sha1_check_values() {
+    assert_eq!(to_hex(&sha1(b"")), "da39a3ee5e6b4b0d3255bfef95601890afd80709");
+    assert_eq!(to_hex(&sha1(b"abc")), "a9993e364706816aba3e25717850c26c9cd0d89d");
+    //Two blocks, because the padding doesn't fit in the first.
+    assert_eq!(to_hex(&sha1(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq")), "84983e441c3bd26ebaae4aa1f95129e5e54670f1");
+  }
+}
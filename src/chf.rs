@@ -0,0 +1,225 @@
+// The .chf cartridge format, modelled after the C64 .crt format. All numbers are big endian.
+//
+// Header:
+//   0x00  16 bytes  "CHANNEL F       "
+//   0x10  4 bytes   Header length
+//   0x14  2 bytes   Version
+//   0x16  2 bytes   Hardware type
+//   0x18  8 bytes   Reserved
+//   0x20  ...       Title, up to the header length, padded with zeros
+//
+// Then any number of chunks:
+//   0x00  4 bytes   "CHIP"
+//   0x04  4 bytes   Length of the chunk, including this 16 byte header
+//   0x08  2 bytes   Chip type: 0 = ROM, 1 = RAM, 2 = Flash
+//   0x0A  2 bytes   Bank
+//   0x0C  2 bytes   Load address
+//   0x0E  2 bytes   Size of the data
+//   0x10  ...       Data
+
+const SIGNATURE: &[u8] = b"CHANNEL F";
+const CARTRIDGE_START: usize = 0x0800;  //Below is the BIOS.
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub(super) enum HardwareType {
+  Videocart,  //Plain ROM
+  Sram2102,   //ROM and a 2102 SRAM on I/O ports
+  Schach,     //ROM and 2K of RAM at 0x2800
+  Multicart,  //Flash with switchable banks
+  Smi3853,    //ROM and a 3853 with its timer and interrupts
+  Unknown(u16),
+}
+
+impl HardwareType {
+  fn from_u16(value: u16) -> Self {
+    match value {
+      0 => HardwareType::Videocart,
+      1 => HardwareType::Sram2102,
+      2 => HardwareType::Schach,
+      3 => HardwareType::Multicart,
+      4 => HardwareType::Smi3853,
+      value => HardwareType::Unknown(value),
+    }
+  }
+}
+
+pub(super) struct Chunk {
+  pub chip_type: u16,
+  pub bank: u16,
+  pub address: u16,
+  pub data: Vec<u8>,
+}
+
+pub(super) struct Chf {
+  pub hardware_type: HardwareType,
+  pub title: String,
+  pub chunks: Vec<Chunk>,
+}
+
+pub(super) fn is_chf(bytes: &[u8]) -> bool {
+  bytes.starts_with(SIGNATURE)
+}
+
+/// Returns None if the header is broken. Broken chunks at the end are dropped.
+pub(super) fn parse(bytes: &[u8]) -> Option<Chf> {
+  if !is_chf(bytes) {
+    return None;
+  }
+  let header_length = read_u32(bytes, 0x10)? as usize;
+  let hardware_type = HardwareType::from_u16(read_u16(bytes, 0x16)?);
+  let title_bytes = bytes.get(0x20..header_length).unwrap_or(&[]);
+  let title_length = title_bytes.iter().position(|&byte| byte == 0).unwrap_or(title_bytes.len());
+  let title = String::from_utf8_lossy(&title_bytes[..title_length]).trim().to_string();
+
+  let mut chunks = vec![];
+  let mut offset = header_length.max(0x20);
+  while bytes.get(offset..offset + 4) == Some(b"CHIP") {
+    let chunk = (|| {
+      let length = read_u32(bytes, offset + 4)? as usize;
+      let size = read_u16(bytes, offset + 0x0E)? as usize;
+      let data = bytes.get(offset + 0x10..offset + 0x10 + size)?.to_vec();
+      Some((length.max(0x10 + size), Chunk {
+        chip_type: read_u16(bytes, offset + 0x08)?,
+        bank: read_u16(bytes, offset + 0x0A)?,
+        address: read_u16(bytes, offset + 0x0C)?,
+        data,
+      }))
+    })();
+    match chunk {
+      Some((length, chunk)) => {
+        chunks.push(chunk);
+        match offset.checked_add(length) {
+          Some(next) => offset = next,
+          None => break,
+        }
+      },
+      None => break,
+    }
+  }
+  Some(Chf { hardware_type, title, chunks })
+}
+
+impl Chf {
+  /// Lays out the ROM chunks of a bank into one flat image, starting from the cartridge address 0x0800. Gaps are filled with 0xFF.
+  pub fn rom_image(&self, bank: u16) -> Vec<u8> {
+    let mut image: Vec<u8> = vec![];
+    for chunk in self.chunks.iter().filter(|chunk| chunk.chip_type != 1 && chunk.bank == bank) {
+      let start = match (chunk.address as usize).checked_sub(CARTRIDGE_START) {
+        Some(start) => start,
+        None => continue, //Can't overwrite the BIOS
+      };
+      let end = start + chunk.data.len();
+      if image.len() < end {
+        image.resize(end, 0xFF);
+      }
+      image[start..end].copy_from_slice(&chunk.data);
+    }
+    image
+  }
+
+  /// All the banks one after another, each padded to bank_size.
+  pub fn banked_image(&self, bank_size: usize) -> Vec<u8> {
+    let bank_count = self.chunks.iter().map(|chunk| chunk.bank).max().map_or(0, |bank| bank + 1);
+    let mut image = vec![];
+    for bank in 0..bank_count {
+      let mut bank_image = self.rom_image(bank);
+      bank_image.resize(bank_size, 0xFF);
+      image.extend_from_slice(&bank_image);
+    }
+    image
+  }
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> Option<u16> {
+  Some(u16::from_be_bytes(bytes.get(offset..offset + 2)?.try_into().ok()?))
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Option<u32> {
+  Some(u32::from_be_bytes(bytes.get(offset..offset + 4)?.try_into().ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn header(hardware_type: u16, title: &str) -> Vec<u8> {
+    let mut bytes = b"CHANNEL F       ".to_vec();
+    bytes.extend_from_slice(&0x30u32.to_be_bytes());
+    bytes.extend_from_slice(&0x0100u16.to_be_bytes());
+    bytes.extend_from_slice(&hardware_type.to_be_bytes());
+    bytes.extend_from_slice(&[0; 8]);
+    let mut title = title.as_bytes().to_vec();
+    title.resize(0x10, 0);
+    bytes.extend_from_slice(&title);
+    bytes
+  }
+
+  fn chunk(chip_type: u16, bank: u16, address: u16, data: &[u8]) -> Vec<u8> {
+    let mut bytes = b"CHIP".to_vec();
+    bytes.extend_from_slice(&(0x10 + data.len() as u32).to_be_bytes());
+    bytes.extend_from_slice(&chip_type.to_be_bytes());
+    bytes.extend_from_slice(&bank.to_be_bytes());
+    bytes.extend_from_slice(&address.to_be_bytes());
+    bytes.extend_from_slice(&(data.len() as u16).to_be_bytes());
+    bytes.extend_from_slice(data);
+    bytes
+  }
+
+  #[test]
+  fn header_and_chunks() {
+    let mut bytes = header(1, "Maze");
+    bytes.extend(chunk(0, 0, 0x0800, &[0x55, 0x2B]));
+    bytes.extend(chunk(1, 0, 0x2800, &[0; 4]));
+    let chf = parse(&bytes).unwrap();
+    assert_eq!(chf.hardware_type, HardwareType::Sram2102);
+    assert_eq!(chf.title, "Maze");
+    assert_eq!(chf.chunks.len(), 2);
+    assert_eq!(chf.chunks[1].address, 0x2800);
+  }
+
+  #[test]
+  fn not_a_chf() {
+    assert!(!is_chf(&[0x55, 0x2B]));
+    assert!(parse(&[0x55, 0x2B]).is_none());
+    assert!(parse(b"CHANNEL F").is_none());
+  }
+
+  #[test]
+  fn rom_image_fills_gaps_and_skips_ram() {
+    let mut bytes = header(0, "");
+    bytes.extend(chunk(0, 0, 0x0804, &[3, 4]));
+    bytes.extend(chunk(0, 0, 0x0800, &[0x55, 1]));
+    bytes.extend(chunk(1, 0, 0x0806, &[9]));
+    bytes.extend(chunk(0, 0, 0x0000, &[7]));
+    let chf = parse(&bytes).unwrap();
+    assert_eq!(chf.rom_image(0), vec![0x55, 1, 0xFF, 0xFF, 3, 4]);
+  }
+
+  #[test]
+  fn banks_are_padded() {
+    let mut bytes = header(3, "");
+    bytes.extend(chunk(2, 0, 0x0800, &[0x55]));
+    bytes.extend(chunk(2, 1, 0x0800, &[0x55, 2]));
+    let chf = parse(&bytes).unwrap();
+    assert_eq!(chf.banked_image(4), vec![0x55, 0xFF, 0xFF, 0xFF, 0x55, 2, 0xFF, 0xFF]);
+  }
+
+  #[test]
+  fn broken_chunk_at_the_end_is_dropped() {
+    let mut bytes = header(0, "");
+    bytes.extend(chunk(0, 0, 0x0800, &[0x55]));
+    let mut broken = chunk(0, 0, 0x0801, &[1, 2, 3]);
+    broken.truncate(broken.len() - 1);
+    bytes.extend(broken);
+    assert_eq!(parse(&bytes).unwrap().chunks.len(), 1);
+  }
+
+  #[test]
+  fn huge_chunk_length_stops_parsing() {
+    let mut bytes = header(0, "");
+    let mut huge = chunk(0, 0, 0x0800, &[0x55]);
+    huge[4..8].copy_from_slice(&u32::MAX.to_be_bytes());
+    bytes.extend(huge);
+    assert_eq!(parse(&bytes).unwrap().chunks.len(), 1);
+  }
+}
@@ -0,0 +1,177 @@
+use wasm_bindgen::prelude::*;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+
+use super::sound;
+use super::loader;
+use super::rom_db;
+use super::memory_editor;
+use super::cheats;
+
+/// Requests coming from the page, other than the controller buttons.
+pub(super) enum Command {
+  AudioConfig(sound::AudioConfig),
+  LoadRom(loader::RomKind, Vec<u8>, Option<String>),  //Bytes of a local file the user picked or dropped, and its name.
+  LoadLink(loader::RomKind, String),
+  Patch(Vec<u8>),
+  PatchLink(String),
+  ChooseArchiveEntry(usize),
+  CartridgeType(Option<rom_db::CartridgeType>), //None goes back to detecting it.
+  ExportSaveRam,
+  ImportSaveRam(Vec<u8>),
+  ClearSaveRam,
+  SaveState,
+  LoadState(Vec<u8>),
+  Eject,
+  Reset,  //The console's Reset button. Only the CPU starts over.
+  PowerCycle, //Off and on again. RAM, VRAM and ports are cleared.
+  Pause,
+  Resume,
+  TogglePause,
+  Step,
+  StepOver,
+  RunToNextFrame,
+  FrameAdvance,
+  AddBreakpoint(String, String, u32), //Address, condition, how many hits before stopping.
+  RemoveBreakpoint(usize),
+  AddWatchpoint(String, String, String, String), //Space, address, access, value.
+  RemoveWatchpoint(usize),
+  LogLevel(String),
+  LogCategory(String, bool),
+  TraceSize(usize),
+  DownloadTrace,
+  CompareTrace(Vec<u8>),  //The reference log.
+  SetRegister(usize, u8), //Scratchpad register, value.
+  ViewMemory(memory_editor::Space, usize),
+  SetMemory(memory_editor::Space, usize, u8),
+  AddCheat(String, String), //Name, code.
+  ToggleCheat(usize),
+  RemoveCheat(usize),
+  FilterCheats(String),
+  StartCheatSearch(cheats::SearchSpace),
+  CheatSearch(cheats::Comparison),
+  MonitorPorts(bool),
+  ClearPortLog,
+}
+
+thread_local! {
+  //Commands from the functions we export to JavaScript, as opposed to the ones the page leaves in getPendingCommand.
+  static QUEUED: RefCell<VecDeque<Command>> = const { RefCell::new(VecDeque::new()) };
+}
+
+pub(super) fn queue(command: Command) {
+  QUEUED.with(|queued| queued.borrow_mut().push_back(command));
+}
+
+pub(super) struct Commands {
+  pending_command_var: wasm_bindgen::JsValue,
+}
+
+impl Commands {
+  pub fn new() -> Self {
+    let pending_command_var = js_sys::Reflect::get(
+      &wasm_bindgen::JsValue::from(web_sys::window().unwrap()),
+      &wasm_bindgen::JsValue::from("getPendingCommand"),
+    ).unwrap();
+
+    Self {
+      pending_command_var,
+    }
+  }
+
+  /// Commands arrive as arrays, with the name of the command first, followed by its arguments.
+  /// Anything we don't understand is skipped.
+  pub fn next(&self) -> Option<Command> {
+    if let Some(command) = QUEUED.with(|queued| queued.borrow_mut().pop_front()) {
+      return Some(command);
+    }
+    let pending_func: &js_sys::Function = self.pending_command_var.dyn_ref().unwrap();
+    loop {
+      let command_var = pending_func.apply(&JsValue::null(), &js_sys::Array::new()).unwrap();
+      let command_array = command_var.dyn_ref::<js_sys::Array>()?;
+      if let Some(command) = parse_command(command_array) {
+        return Some(command);
+      }
+    }
+  }
+}
+
+fn parse_command(command_array: &js_sys::Array) -> Option<Command> {
+  let name = command_array.get(0).as_string()?;
+  match name.as_str() {
+    "audio_config" => Some(Command::AudioConfig(sound::AudioConfig {
+      latency_ms: get_u32(command_array, 1)?,
+      buffer_count: get_u32(command_array, 2)?,
+      buffer_size: get_u32(command_array, 3)?,
+    })),
+    "load" => {
+      let kind = loader::RomKind::from_name(&command_array.get(1).as_string()?)?;
+      let bytes = command_array.get(2).dyn_into::<js_sys::Uint8Array>().ok()?.to_vec();
+      Some(Command::LoadRom(kind, bytes, command_array.get(3).as_string()))
+    },
+    "load_link" => {
+      let kind = loader::RomKind::from_name(&command_array.get(1).as_string()?)?;
+      Some(Command::LoadLink(kind, command_array.get(2).as_string()?))
+    },
+    "patch" => Some(Command::Patch(command_array.get(1).dyn_into::<js_sys::Uint8Array>().ok()?.to_vec())),
+    "patch_link" => Some(Command::PatchLink(command_array.get(1).as_string()?)),
+    "cartridge_type" => Some(Command::CartridgeType(rom_db::CartridgeType::from_name(&command_array.get(1).as_string()?))),
+    "archive_pick" => Some(Command::ChooseArchiveEntry(get_u32(command_array, 1)? as usize)),
+    "ram_export" => Some(Command::ExportSaveRam),
+    "ram_import" => Some(Command::ImportSaveRam(command_array.get(1).dyn_into::<js_sys::Uint8Array>().ok()?.to_vec())),
+    "ram_clear" => Some(Command::ClearSaveRam),
+    "state_save" => Some(Command::SaveState),
+    "state_load" => Some(Command::LoadState(command_array.get(1).dyn_into::<js_sys::Uint8Array>().ok()?.to_vec())),
+    "eject" => Some(Command::Eject),
+    "reset" => Some(Command::Reset),
+    "power_cycle" => Some(Command::PowerCycle),
+    "pause" => Some(Command::Pause),
+    "resume" => Some(Command::Resume),
+    "toggle_pause" => Some(Command::TogglePause),
+    "step" => Some(Command::Step),
+    "step_over" => Some(Command::StepOver),
+    "run_to_next_frame" => Some(Command::RunToNextFrame),
+    "frame_advance" => Some(Command::FrameAdvance),
+    "breakpoint_add" => Some(Command::AddBreakpoint(
+      command_array.get(1).as_string()?,
+      command_array.get(2).as_string().unwrap_or_default(),
+      get_u32(command_array, 3).unwrap_or(1),
+    )),
+    "breakpoint_remove" => Some(Command::RemoveBreakpoint(get_u32(command_array, 1)? as usize)),
+    "watchpoint_add" => Some(Command::AddWatchpoint(
+      command_array.get(1).as_string()?,
+      command_array.get(2).as_string()?,
+      command_array.get(3).as_string().unwrap_or_else(|| "rw".to_string()),
+      command_array.get(4).as_string().unwrap_or_default(),
+    )),
+    "watchpoint_remove" => Some(Command::RemoveWatchpoint(get_u32(command_array, 1)? as usize)),
+    "log_level" => Some(Command::LogLevel(command_array.get(1).as_string()?)),
+    "log_category" => Some(Command::LogCategory(command_array.get(1).as_string()?, command_array.get(2).as_bool()?)),
+    "trace_size" => Some(Command::TraceSize(get_u32(command_array, 1)? as usize)),
+    "trace_download" => Some(Command::DownloadTrace),
+    "register_set" => Some(Command::SetRegister(get_u32(command_array, 1)? as usize, get_u32(command_array, 2)? as u8)),
+    "memory_view" => Some(Command::ViewMemory(
+      memory_editor::Space::from_name(&command_array.get(1).as_string()?)?,
+      get_u32(command_array, 2)? as usize,
+    )),
+    "memory_set" => Some(Command::SetMemory(
+      memory_editor::Space::from_name(&command_array.get(1).as_string()?)?,
+      get_u32(command_array, 2)? as usize,
+      get_u32(command_array, 3)? as u8,
+    )),
+    "cheat_add" => Some(Command::AddCheat(command_array.get(1).as_string().unwrap_or_default(), command_array.get(2).as_string()?)),
+    "cheat_toggle" => Some(Command::ToggleCheat(get_u32(command_array, 1)? as usize)),
+    "cheat_remove" => Some(Command::RemoveCheat(get_u32(command_array, 1)? as usize)),
+    "cheat_filter" => Some(Command::FilterCheats(command_array.get(1).as_string()?)),
+    "cheat_search_start" => Some(Command::StartCheatSearch(cheats::SearchSpace::from_name(&command_array.get(1).as_string()?)?)),
+    "cheat_search" => Some(Command::CheatSearch(cheats::Comparison::from_name(&command_array.get(1).as_string()?)?)),
+    "port_monitor" => Some(Command::MonitorPorts(command_array.get(1).as_bool()?)),
+    "port_log_clear" => Some(Command::ClearPortLog),
+    "trace_compare" => Some(Command::CompareTrace(command_array.get(1).dyn_into::<js_sys::Uint8Array>().ok()?.to_vec())),
+    _ => None,
+  }
+}
+
+fn get_u32(command_array: &js_sys::Array, index: u32) -> Option<u32> {
+  command_array.get(index).as_f64().map(|value| value.max(0.0).round() as u32)
+}
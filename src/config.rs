@@ -0,0 +1,134 @@
+use super::keyboard;
+use super::scheduler;
+use super::trace;
+use super::video;
+
+// Everything the page can be told through its URL. Values must be percent encoded, like any URL.
+// A parameter that is missing or not understood is left at its default. The full list is in the README.
+
+const MAX_SCALE: u32 = 4;
+
+pub(super) struct Config {
+  pub bios: Option<String>, //Link to the BIOS.
+  pub rom: Option<String>,  //Link to the cartridge.
+  pub patch: Option<String>,  //Link to an IPS or BPS patch for the cartridge.
+  pub pacing: scheduler::Pacing,
+  pub timing: scheduler::Timing,
+  pub palette: video::Palette,
+  pub scale: u32,
+  pub volume: f32,  //0.0 to 1.0
+  pub keys: keyboard::KeyPreset,
+  pub autostart: Vec<u8>, //Button codes to press once the BIOS is up, usually to pick a game.
+  pub state: Option<String>,  //Link to a save state, put in place right after power on.
+  pub log: String,  //The lowest log level shown in the console.
+  pub trace: usize, //How many instructions to keep for the trace download.
+}
+
+impl Config {
+  pub fn from_query_string(query_string: &str) -> Self {
+    let params = parse_query_string(query_string);
+    Self {
+      bios: params.get("bios").filter(|link| !link.is_empty()).cloned(),
+      rom: params.get("rom").filter(|link| !link.is_empty()).cloned(),
+      patch: params.get("patch").filter(|link| !link.is_empty()).cloned(),
+      pacing: scheduler::Pacing::from_param(params.get("pacing")),
+      timing: scheduler::Timing::from_param(params.get("region")),
+      palette: video::Palette::from_param(params.get("palette")),
+      scale: params.get("scale").and_then(|scale| scale.parse().ok()).unwrap_or(1).clamp(1, MAX_SCALE),
+      volume: params.get("volume").and_then(|volume| volume.parse::<u32>().ok()).map_or(1.0, |volume| volume.min(100) as f32 / 100.0),
+      keys: keyboard::KeyPreset::from_param(params.get("keys")),
+      autostart: params.get("autostart").map_or(vec![], |buttons| parse_buttons(buttons)),
+      state: params.get("state").filter(|link| !link.is_empty()).cloned(),
+      log: params.get("log").cloned().unwrap_or_else(|| "warn".to_string()),
+      trace: params.get("trace").and_then(|size| size.parse().ok()).unwrap_or(0).min(trace::MAX_SIZE),
+    }
+  }
+}
+
+/// Button codes separated by commas. Anything which isn't a button is left out.
+fn parse_buttons(buttons: &str) -> Vec<u8> {
+  buttons.split(',').filter_map(|code| code.trim().parse().ok()).filter(|code| keyboard::BUTTON_CODES.contains(code)).collect()
+}
+
+fn parse_query_string(query_string: &str) -> std::collections::HashMap<String, String> {
+  let mut result = std::collections::HashMap::new();
+
+  for pair in query_string.trim_start_matches('?').split('&') {
+    //Only split on the first '='. Links can have their own.
+    if let Some((key, value)) = pair.split_once('=') {
+      result.insert(percent_decode(key), percent_decode(value));
+    }
+  }
+
+  result
+}
+
+/// Turns %XX back into bytes and + into spaces. A % which isn't followed by two hex digits is kept as it is.
+fn percent_decode(text: &str) -> String {
+  let bytes = text.as_bytes();
+  let mut decoded = Vec::with_capacity(bytes.len());
+  let mut index = 0;
+  while index < bytes.len() {
+    match bytes[index] {
+      b'+' => decoded.push(b' '),
+      b'%' => {
+        let hex = text.get(index + 1..index + 3)
+          .filter(|hex| hex.bytes().all(|digit| digit.is_ascii_hexdigit()))
+          .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match hex {
+          Some(byte) => {
+            decoded.push(byte);
+            index += 2;
+          },
+          None => decoded.push(b'%'),
+        }
+      },
+      byte => decoded.push(byte),
+    }
+    index += 1;
+  }
+  String::from_utf8_lossy(&decoded).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn percent_escapes_and_plus() {
+    assert_eq!(percent_decode("Videocart%2010%20-%20Maze.bin"), "Videocart 10 - Maze.bin");
+    assert_eq!(percent_decode("a+b%2Bc"), "a b+c");
+    assert_eq!(percent_decode("%2f%2F"), "//");
+  }
+
+  #[test]
+  fn bad_escapes_are_kept() {
+    assert_eq!(percent_decode("100%"), "100%");
+    assert_eq!(percent_decode("%4"), "%4");
+    assert_eq!(percent_decode("%GG%zz"), "%GG%zz");
+    assert_eq!(percent_decode("%%41"), "%A");
+  }
+
+  #[test]
+  fn multi_byte_utf8() {
+    assert_eq!(percent_decode("Sch%C3%A4ch"), "Schäch");
+    assert_eq!(percent_decode("%E2%98%83"), "\u{2603}");
+    assert_eq!(percent_decode("ä%C3"), "ä\u{FFFD}"); //Cut off in the middle of a character.
+  }
+
+  #[test]
+  fn query_string() {
+    let params = parse_query_string("?rom=http%3A%2F%2Fexample.com%2Fgame.bin%3Fv%3D1&scale=2&empty=&flag");
+    assert_eq!(params.get("rom").map(String::as_str), Some("http://example.com/game.bin?v=1"));
+    assert_eq!(params.get("scale").map(String::as_str), Some("2"));
+    assert_eq!(params.get("empty").map(String::as_str), Some(""));
+    assert_eq!(params.get("flag"), None);
+    //Only the first = splits, so links keep their own.
+    assert_eq!(parse_query_string("rom=game.bin?a=b").get("rom").map(String::as_str), Some("game.bin?a=b"));
+  }
+
+  #[test]
+  fn only_buttons_autostart() {
+    assert_eq!(parse_buttons("1, 12,0,13,255,x,,4"), vec![1, 12, 4]);
+  }
+}
@@ -0,0 +1,318 @@
+use chips::fairchild_f8;
+use super::breakpoints;
+use super::disassembler;
+use super::watchpoints;
+use super::html;
+
+// Pausing and stepping through the program. The debugger is asked after every instruction whether to stop.
+// While paused, the main loop only runs what the debugger asks for, one frame at a time at most, so the page keeps updating.
+
+const PI: u8 = 0x28;  //Push and jump. 3 bytes.
+const PK: u8 = 0x0C;  //Push and jump to K. 1 byte.
+const CODE_BEFORE: usize = 6; //Instructions shown before PC0 in the code view.
+const CODE_AFTER: usize = 12;
+const CARTRIDGE_START: u16 = 0x0800;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Mode {
+  Running,
+  Paused,
+  Step,
+  StepOver(u16),  //Run until PC0 comes back to this address.
+  RunToFrameEnd(u32), //How many more frame ends to run through.
+}
+
+pub(super) struct Debugger {
+  mode: Mode,
+  breakpoints: Vec<breakpoints::Breakpoint>,
+  status: Option<web_sys::Element>,
+  status_text: String,
+  breakpoint_list: Option<web_sys::Element>,
+  breakpoint_html: String,
+  breakpoint_error: Option<web_sys::Element>,
+  watchpoints: Vec<watchpoints::Watchpoint>,
+  accesses: Vec<(watchpoints::Space, u16, watchpoints::Access)>, //What the instruction now running reads and writes.
+  watchpoint_list: Option<web_sys::Element>,
+  watchpoint_html: String,
+  watchpoint_error: Option<web_sys::Element>,
+  stop_reason: Option<String>,
+  code: Option<web_sys::Element>,
+  code_html: String,
+}
+
+impl Debugger {
+  pub fn new() -> Self {
+    let document = web_sys::window().unwrap().document().unwrap();
+    Self {
+      mode: Mode::Running,
+      breakpoints: vec![],
+      status: document.get_element_by_id("debug_status"),
+      status_text: String::new(),
+      breakpoint_list: document.get_element_by_id("breakpoint_list"),
+      breakpoint_html: String::new(),
+      breakpoint_error: document.get_element_by_id("breakpoint_error"),
+      watchpoints: vec![],
+      accesses: vec![],
+      watchpoint_list: document.get_element_by_id("watchpoint_list"),
+      watchpoint_html: String::new(),
+      watchpoint_error: document.get_element_by_id("watchpoint_error"),
+      stop_reason: None,
+      code: document.get_element_by_id("code_list"),
+      code_html: String::new(),
+    }
+  }
+
+  pub fn is_running(&self) -> bool {
+    self.mode == Mode::Running
+  }
+
+  /// How many frames to let the main loop run while not running freely.
+  pub fn frames_to_run(&self) -> u32 {
+    if self.mode == Mode::Paused { 0 } else { 1 }
+  }
+
+  pub fn pause(&mut self) {
+    self.mode = Mode::Paused;
+  }
+
+  pub fn resume(&mut self) {
+    self.mode = Mode::Running;
+    self.stop_reason = None;
+  }
+
+  /// Pauses for something outside the debugger, which is shown as the reason.
+  pub fn stop(&mut self, board: &fairchild_f8::Board, reason: String) {
+    log::info!(target: "debugger", "Paused at {:04X}, {}", board.roms[0].pc0, reason);
+    self.mode = Mode::Paused;
+    self.stop_reason = Some(reason);
+  }
+
+  pub fn toggle_pause(&mut self) {
+    self.mode = if self.mode == Mode::Running { Mode::Paused } else { Mode::Running };
+    self.stop_reason = None;
+  }
+
+  pub fn step(&mut self) {
+    self.mode = Mode::Step;
+  }
+
+  /// Runs a whole subroutine if the next instruction calls one. Otherwise the same as a step.
+  pub fn step_over(&mut self, board: &fairchild_f8::Board) {
+    let pc0 = board.roms[0].pc0;
+    self.mode = match board.read_memory(pc0) {
+      PI => Mode::StepOver(pc0.wrapping_add(3)),
+      PK => Mode::StepOver(pc0.wrapping_add(1)),
+      _ => Mode::Step,
+    };
+  }
+
+  /// Runs until the frame being drawn is finished.
+  pub fn run_to_next_frame(&mut self) {
+    self.mode = Mode::RunToFrameEnd(1);
+  }
+
+  /// Runs one whole frame. If we stopped in the middle of a frame, that one is finished first.
+  pub fn frame_advance(&mut self, in_frame: bool) {
+    self.mode = Mode::RunToFrameEnd(if in_frame { 2 } else { 1 });
+  }
+
+  /// Shows what was wrong with the breakpoint, if anything.
+  pub fn add_breakpoint(&mut self, address: &str, condition: &str, stop_after: u32) {
+    let breakpoint = breakpoints::parse_number(address)
+      .ok_or_else(|| format!("\"{}\" is not an address", address))
+      .and_then(|address| breakpoints::Breakpoint::new(address, condition, stop_after));
+    let error = match breakpoint {
+      Ok(breakpoint) => {
+        self.breakpoints.push(breakpoint);
+        String::new()
+      },
+      Err(error) => error,
+    };
+    if let Some(element) = &self.breakpoint_error {
+      element.set_text_content(Some(&error));
+    }
+  }
+
+  pub fn remove_breakpoint(&mut self, index: usize) {
+    if index < self.breakpoints.len() {
+      self.breakpoints.remove(index);
+    }
+  }
+
+  /// The address is "x,y" for VRAM. An empty value means any value.
+  /// Shows what was wrong with the watchpoint, if anything.
+  pub fn add_watchpoint(&mut self, board: &fairchild_f8::Board, space: &str, address: &str, access: &str, value: &str) {
+    let watchpoint = (|| {
+      let space = watchpoints::Space::from_name(space).ok_or_else(|| format!("\"{}\" is not a memory space", space))?;
+      let address = match (space, address.split_once(',')) {
+        (watchpoints::Space::Vram, Some((x, y))) => {
+          match (breakpoints::parse_number(x), breakpoints::parse_number(y)) {
+            (Some(x), Some(y)) if x < 128 && y < 64 => y * 128 + x,
+            _ => return Err(format!("\"{}\" is not a pixel. VRAM is 128 x 64", address)),
+          }
+        },
+        (watchpoints::Space::Vram, None) => return Err(format!("\"{}\" should be a pixel, like 10,20", address)),
+        _ => breakpoints::parse_number(address).ok_or_else(|| format!("\"{}\" is not an address", address))?,
+      };
+      match space {
+        watchpoints::Space::Scratchpad if address >= 64 => return Err("The scratchpad has registers 0 to 63".to_string()),
+        watchpoints::Space::Port if address >= 256 => return Err("Ports go from 0 to 0xFF".to_string()),
+        _ => {},
+      }
+      let access = watchpoints::Access::from_name(access).ok_or_else(|| format!("\"{}\" is not read, write or both", access))?;
+      let value = match value.trim() {
+        "" => None,
+        value => match breakpoints::parse_number(value) {
+          Some(value) if value < 256 => Some(value as u8),
+          _ => return Err(format!("\"{}\" is not a byte", value)),
+        },
+      };
+      Ok(watchpoints::Watchpoint::new(space, address, access, value))
+    })();
+    let error = match watchpoint {
+      Ok(watchpoint) => {
+        //Accesses are only decoded while there are watchpoints. The instruction about to run is the first to be checked.
+        if self.watchpoints.is_empty() {
+          self.accesses = watchpoints::decode_accesses(board);
+        }
+        self.watchpoints.push(watchpoint);
+        String::new()
+      },
+      Err(error) => error,
+    };
+    if let Some(element) = &self.watchpoint_error {
+      element.set_text_content(Some(&error));
+    }
+  }
+
+  pub fn remove_watchpoint(&mut self, index: usize) {
+    if index < self.watchpoints.len() {
+      self.watchpoints.remove(index);
+    }
+  }
+
+  /// Called after every instruction. Returns true if we should stop here.
+  pub fn run_cycle(&mut self, board: &fairchild_f8::Board, frame_done: bool) -> bool {
+    let mut reason = None;
+    let mut stop = match self.mode {
+      Mode::Running | Mode::Paused => false,
+      Mode::Step => true,
+      Mode::StepOver(address) => board.roms[0].pc0 == address,
+      Mode::RunToFrameEnd(frames) => {
+        if frame_done {
+          self.mode = Mode::RunToFrameEnd(frames - 1);
+        }
+        frame_done && frames == 1
+      },
+    };
+    if let Some(breakpoint_reason) = self.check_breakpoints(board) {
+      stop = true;
+      reason = Some(breakpoint_reason);
+    }
+    if !self.watchpoints.is_empty() {
+      for watchpoint in &mut self.watchpoints {
+        if watchpoint.check(board, &self.accesses) {
+          stop = true;
+          reason = Some(format!("watchpoint {}", watchpoint.describe()));
+        }
+      }
+      self.accesses = watchpoints::decode_accesses(board);
+    }
+    if stop {
+      if let Some(reason) = &reason {
+        log::info!(target: "debugger", "Paused at {:04X}, {}", board.roms[0].pc0, reason);
+      }
+      self.mode = Mode::Paused;
+      self.stop_reason = reason;
+    }
+    stop
+  }
+
+  /// Called on a new board. Its first instruction, at 0000, comes before any run_cycle, so a breakpoint there is checked here,
+  /// and the accesses of that instruction are decoded.
+  pub fn power_on(&mut self, board: &fairchild_f8::Board) {
+    //What was decoded on the old board never runs.
+    self.accesses = if self.watchpoints.is_empty() { vec![] } else { watchpoints::decode_accesses(board) };
+    if let Some(reason) = self.check_breakpoints(board) {
+      self.stop(board, reason);
+    }
+  }
+
+  /// Counts the hits of the breakpoints at PC0. Returns why to stop, if one of them says so.
+  fn check_breakpoints(&mut self, board: &fairchild_f8::Board) -> Option<String> {
+    let pc0 = board.roms[0].pc0;
+    let mut reason = None;
+    //Every breakpoint at this address counts its hit, even if an earlier one already stops us.
+    for breakpoint in self.breakpoints.iter_mut().filter(|breakpoint| breakpoint.address == pc0) {
+      if breakpoint.hit(board) {
+        reason = Some(format!("breakpoint at {:04X}", pc0));
+      }
+    }
+    reason
+  }
+
+  pub fn print(&mut self, board: &fairchild_f8::Board) {
+    let text = match (self.mode, &self.stop_reason) {
+      (Mode::Running, _) => "Running".to_string(),
+      (_, Some(reason)) => format!("Paused at {:04X}, {}", board.roms[0].pc0, reason),
+      (_, None) => format!("Paused at {:04X}", board.roms[0].pc0),
+    };
+    if self.status_text != text {
+      if let Some(element) = &self.status {
+        element.set_text_content(Some(&text));
+      }
+      self.status_text = text;
+    }
+
+    let mut html = String::new();
+    for (index, breakpoint) in self.breakpoints.iter().enumerate() {
+      html.push_str(&format!(
+        "<tr><td>{:04X}</td><td>{}</td><td>{} / {}</td><td><button onclick=\"pendingCommands.push(['breakpoint_remove', {}])\">X</button></td></tr>",
+        breakpoint.address, html::escape(&breakpoint.condition_text), breakpoint.hits, breakpoint.stop_after, index,
+      ));
+    }
+    if self.breakpoint_html != html {
+      if let Some(element) = &self.breakpoint_list {
+        element.set_inner_html(&html);
+      }
+      self.breakpoint_html = html;
+    }
+
+    let mut html = String::new();
+    for (index, watchpoint) in self.watchpoints.iter().enumerate() {
+      html.push_str(&format!(
+        "<tr><td>{}</td><td>{}</td><td><button onclick=\"pendingCommands.push(['watchpoint_remove', {}])\">X</button></td></tr>",
+        watchpoint.describe(), watchpoint.hits, index,
+      ));
+    }
+    if self.watchpoint_html != html {
+      if let Some(element) = &self.watchpoint_list {
+        element.set_inner_html(&html);
+      }
+      self.watchpoint_html = html;
+    }
+
+    self.print_code(board);
+  }
+
+  /// The code around PC0, with the instruction about to run highlighted and breakpoints marked.
+  fn print_code(&mut self, board: &fairchild_f8::Board) {
+    let pc0 = board.roms[0].pc0;
+    let mut html = String::new();
+    for instruction in disassembler::around(|address| board.read_memory(address), pc0, CODE_BEFORE, CODE_AFTER) {
+      let class = if instruction.address == pc0 { " class=\"pc\"" } else { "" };
+      let breakpoint = if self.breakpoints.iter().any(|breakpoint| breakpoint.address == instruction.address) { "&#9679;" } else { "" };
+      let region = if instruction.address < CARTRIDGE_START { "BIOS" } else { "Cart" };
+      html.push_str(&format!(
+        "<tr{}><td>{}</td><td>{}</td><td>{:04X}</td><td>{}</td></tr>",
+        class, breakpoint, region, instruction.address, instruction.text,
+      ));
+    }
+    if self.code_html != html {
+      if let Some(element) = &self.code {
+        element.set_inner_html(&html);
+      }
+      self.code_html = html;
+    }
+  }
+}
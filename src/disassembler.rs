@@ -0,0 +1,202 @@
+// Turns F8 machine code back into assembly, for the code view in the debugger and for dumping whole ROMs.
+//
+// Instructions are 1 to 3 bytes. Branches are relative to the address of their displacement byte,
+// so we show where they go instead, the same as jumps.
+// Scratchpad registers 0 - 11 are r0 - r11. 12 - 14 mean the register the ISAR points to, then leave it as is,
+// increment it or decrement it: (IS), (IS)+ and (IS)-.
+
+pub(super) struct Instruction {
+  pub address: u16,
+  pub size: u16,
+  pub text: String,
+}
+
+/// Decodes the instruction at an address. read gives the byte at any address.
+pub(super) fn decode(read: impl Fn(u16) -> u8, address: u16) -> Instruction {
+  let opcode = read(address);
+  let byte = read(address.wrapping_add(1));
+  let word = ((byte as u16) << 8) | read(address.wrapping_add(2)) as u16;
+  let branch = address.wrapping_add(1).wrapping_add(byte as i8 as u16);
+  let low = opcode & 0x0F;
+  let (size, text) = match opcode {
+    0x00..=0x2C => match FIXED[opcode as usize] {
+      (name, 2) => (2, format!("{} 0x{:02X}", name, byte)),
+      (name, 3) => (3, format!("{} 0x{:04X}", name, word)),
+      (name, _) => (1, name.to_string()),
+    },
+    0x30..=0x3E => (1, format!("DS {}", register(low))),
+    0x40..=0x4E => (1, format!("LR A,{}", register(low))),
+    0x50..=0x5E => (1, format!("LR {},A", register(low))),
+    0x60..=0x67 => (1, format!("LISU {}", low)),
+    0x68..=0x6F => (1, format!("LISL {}", low & 0x07)),
+    0x70 => (1, "CLR".to_string()),
+    0x71..=0x7F => (1, format!("LIS 0x{:X}", low)),
+    0x80..=0x87 => (2, format!("{} 0x{:04X}", branch_true(low), branch)),
+    0x88..=0x8E => (1, ["AM", "AMD", "NM", "OM", "XM", "CM", "ADC"][low as usize - 8].to_string()),
+    0x8F => (2, format!("BR7 0x{:04X}", branch)),
+    0x90..=0x9F => (2, format!("{} 0x{:04X}", branch_false(low), branch)),
+    0xA0..=0xAF => (1, format!("INS {}", low)),
+    0xB0..=0xBF => (1, format!("OUTS {}", low)),
+    0xC0..=0xCE => (1, format!("AS {}", register(low))),
+    0xD0..=0xDE => (1, format!("ASD {}", register(low))),
+    0xE0..=0xEE => (1, format!("XS {}", register(low))),
+    0xF0..=0xFE => (1, format!("NS {}", register(low))),
+    _ => (1, format!("DB 0x{:02X}", opcode)), //Not an instruction.
+  };
+  Instruction { address, size, text }
+}
+
+/// One line per instruction, with its address and bytes. For dumping a whole ROM loaded at origin.
+pub(super) fn disassemble(bytes: &[u8], origin: u16) -> String {
+  let read = |address: u16| bytes.get(address.wrapping_sub(origin) as usize).copied().unwrap_or(0);
+  let mut text = String::new();
+  let mut offset = 0;
+  while offset < bytes.len() {
+    let mut instruction = decode(read, origin.wrapping_add(offset as u16));
+    //An instruction cut off by the end of the ROM is shown as data.
+    if offset + instruction.size as usize > bytes.len() {
+      instruction = Instruction { size: 1, text: format!("DB 0x{:02X}", bytes[offset]), ..instruction };
+    }
+    let end = offset + instruction.size as usize;
+    let hex: Vec<String> = bytes[offset..end].iter().map(|byte| format!("{:02X}", byte)).collect();
+    text.push_str(&format!("{:04X}  {:<9} {}\n", instruction.address, hex.join(" "), instruction.text));
+    offset = end;
+  }
+  text
+}
+
+/// The instructions before and after an address. Code can't be decoded backwards,
+/// so we try starting a little earlier until the instructions line up with the address.
+pub(super) fn around(read: impl Fn(u16) -> u8, address: u16, before: usize, after: usize) -> Vec<Instruction> {
+  let mut previous = vec![];
+  for distance in (1..=before as u16 * 3).rev() {
+    let mut instructions = vec![];
+    let mut current = address.wrapping_sub(distance);
+    while current.wrapping_sub(address.wrapping_sub(distance)) < distance {
+      let instruction = decode(&read, current);
+      current = current.wrapping_add(instruction.size);
+      instructions.push(instruction);
+    }
+    if current == address && instructions.len() >= before {
+      previous = instructions.split_off(instructions.len() - before);
+      break;
+    }
+    if current == address && instructions.len() > previous.len() {
+      previous = instructions;
+    }
+  }
+  let mut current = address;
+  for _ in 0..=after {
+    let instruction = decode(&read, current);
+    current = current.wrapping_add(instruction.size);
+    previous.push(instruction);
+  }
+  previous
+}
+
+/// Opcodes 0x00 - 0x2C: name and size.
+const FIXED: [(&str, u16); 0x2D] = [
+  ("LR A,KU", 1), ("LR A,KL", 1), ("LR A,QU", 1), ("LR A,QL", 1),
+  ("LR KU,A", 1), ("LR KL,A", 1), ("LR QU,A", 1), ("LR QL,A", 1),
+  ("LR K,P", 1), ("LR P,K", 1), ("LR A,IS", 1), ("LR IS,A", 1),
+  ("PK", 1), ("LR P0,Q", 1), ("LR Q,DC", 1), ("LR DC,Q", 1),
+  ("LR DC,H", 1), ("LR H,DC", 1), ("SR 1", 1), ("SL 1", 1),
+  ("SR 4", 1), ("SL 4", 1), ("LM", 1), ("ST", 1),
+  ("COM", 1), ("LNK", 1), ("DI", 1), ("EI", 1),
+  ("POP", 1), ("LR W,J", 1), ("LR J,W", 1), ("INC", 1),
+  ("LI", 2), ("NI", 2), ("OI", 2), ("XI", 2),
+  ("AI", 2), ("CI", 2), ("IN", 2), ("OUT", 2),
+  ("PI", 3), ("JMP", 3), ("DCI", 3), ("NOP", 1),
+  ("XDC", 1),
+];
+
+fn register(nibble: u8) -> String {
+  match nibble {
+    12 => "(IS)".to_string(),
+    13 => "(IS)+".to_string(),
+    14 => "(IS)-".to_string(),
+    _ => format!("r{}", nibble),
+  }
+}
+
+/// Branches if any of the tested flags are set. 1 is sign (positive), 2 carry and 4 zero.
+fn branch_true(test: u8) -> String {
+  match test {
+    1 => "BP".to_string(),
+    2 => "BC".to_string(),
+    4 => "BZ".to_string(),
+    _ => format!("BT {},", test),
+  }
+}
+
+/// Branches if all of the tested flags are clear. 1 is sign (negative), 2 carry, 4 zero and 8 overflow.
+fn branch_false(test: u8) -> String {
+  match test {
+    0 => "BR".to_string(),
+    1 => "BM".to_string(),
+    2 => "BNC".to_string(),
+    4 => "BNZ".to_string(),
+    8 => "BNO".to_string(),
+    _ => format!("BF {},", test),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use super::super::free_bios;
+
+  /// Reads the free BIOS, whose boot code is listed in its module.
+  fn free_bios() -> impl Fn(u16) -> u8 {
+    let image = free_bios::image();
+    move |address| image.get(address as usize).copied().unwrap_or(0)
+  }
+
+  #[test]
+  fn sizes_and_operands() {
+    let read = free_bios();
+    let texts: Vec<(u16, u16, String)> = [0x0000, 0x0001, 0x0002, 0x0005, 0x0006, 0x0009, 0x000A].iter()
+      .map(|&address| { let instruction = decode(&read, address); (instruction.address, instruction.size, instruction.text) })
+      .collect();
+    assert_eq!(texts, vec![
+      (0x0000, 1, "DI".to_string()),
+      (0x0001, 1, "CLR".to_string()),
+      (0x0002, 1, "OUTS 0".to_string()),
+      (0x0005, 1, "OUTS 5".to_string()),
+      (0x0006, 3, "DCI 0x0800".to_string()),
+      (0x0009, 1, "LM".to_string()),
+      (0x000A, 2, "CI 0x55".to_string()),
+    ]);
+  }
+
+  #[test]
+  fn branches_and_jumps_show_where_they_go() {
+    let read = free_bios();
+    assert_eq!(decode(&read, 0x000C).text, "BNZ 0x0011");
+    assert_eq!(decode(&read, 0x000E).text, "JMP 0x0802");
+    assert_eq!(decode(&read, 0x0011).text, "BR 0x0011");
+    assert_eq!(decode(&read, 0x0013).text, "POP");
+  }
+
+  #[test]
+  fn registers_through_the_isar() {
+    let read = |address: u16| [0x4C, 0x5D, 0x3E, 0xC3].get(address as usize).copied().unwrap_or(0);
+    let texts: Vec<String> = (0..4).map(|address| decode(read, address).text).collect();
+    assert_eq!(texts, ["LR A,(IS)", "LR (IS)+,A", "DS (IS)-", "AS r3"]);
+    assert_eq!(decode(|_| 0x2D, 0).text, "DB 0x2D");
+  }
+
+  #[test]
+  fn dump_shows_cut_off_instructions_as_data() {
+    let text = disassemble(&[0x55, 0x29, 0x08], 0x0800);
+    assert_eq!(text, "0800  55        LR r5,A\n0801  29        DB 0x29\n0802  08        LR K,P\n");
+  }
+
+  #[test]
+  fn around_lines_up_with_the_address() {
+    let instructions = around(free_bios(), 0x000C, 2, 1);
+    let addresses: Vec<u16> = instructions.iter().map(|instruction| instruction.address).collect();
+    assert_eq!(addresses, vec![0x0009, 0x000A, 0x000C, 0x000E]);
+  }
+}
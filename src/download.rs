@@ -0,0 +1,26 @@
+use wasm_bindgen::prelude::*;
+
+const REVOKE_DELAY: i32 = 1000; //Milliseconds
+
+/// Makes the browser save the bytes as a file.
+pub(super) fn download(file_name: &str, bytes: &[u8]) {
+  let document = web_sys::window().unwrap().document().unwrap();
+  let parts = js_sys::Array::of1(&js_sys::Uint8Array::from(bytes));
+  let mut options = web_sys::BlobPropertyBag::new();
+  options.type_("application/octet-stream");
+  if let Ok(blob) = web_sys::Blob::new_with_u8_array_sequence_and_options(&parts, &options) {
+    if let Ok(url) = web_sys::Url::create_object_url_with_blob(&blob) {
+      if let Ok(element) = document.create_element("a") {
+        let anchor: &web_sys::HtmlAnchorElement = element.dyn_ref().unwrap();
+        anchor.set_href(&url);
+        anchor.set_download(file_name);
+        anchor.click();
+      }
+      //The browser may still be reading from the URL right after click() returns, so it is let go of a little later.
+      let revoke = Closure::once_into_js(move || {
+        let _ = web_sys::Url::revoke_object_url(&url);
+      });
+      let _ = web_sys::window().unwrap().set_timeout_with_callback_and_timeout_and_arguments_0(revoke.unchecked_ref(), REVOKE_DELAY);
+    }
+  }
+}
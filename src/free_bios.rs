@@ -0,0 +1,70 @@
+use chips::fairchild_f8;
+
+// A free stand-in for the BIOS, used when the user doesn't give one. It was written for this emulator and can be shared freely.
+//
+// The real BIOS draws the title screen and its built-in games, and has routines cartridges call, like drawing characters.
+// This one only does what is needed to start a cartridge:
+//
+//   0000  1A        DI              ;No interrupts until the cartridge wants them.
+//   0001  70        CLR
+//   0002  B0        OUTS 0          ;Console buttons and video strobe
+//   0003  B1        OUTS 1          ;Right controller and pixel color
+//   0004  B4        OUTS 4          ;Left controller and pixel column
+//   0005  B5        OUTS 5          ;Pixel row and tone. Silence.
+//   0006  2A 08 00  DCI 0x0800
+//   0009  16        LM              ;The first byte of a cartridge is 0x55.
+//   000A  25 55     CI 0x55
+//   000C  94 04     BNZ no_cartridge
+//   000E  29 08 02  JMP 0x0802      ;The program starts right after it.
+//   0011  90 FF     no_cartridge: BR no_cartridge
+//
+// Everything else is POP. A cartridge calling a BIOS routine with PI returns right away, instead of running into garbage.
+// Homebrew with its own routines works fully. Games which draw with the BIOS routines won't show much, so the first call
+// into one pauses the game and says that the real BIOS is needed. Resuming carries on without the routine.
+
+const BOOT: &[u8] = &[
+  0x1A,
+  0x70,
+  0xB0,
+  0xB1,
+  0xB4,
+  0xB5,
+  0x2A, 0x08, 0x00,
+  0x16,
+  0x25, 0x55,
+  0x94, 0x04,
+  0x29, 0x08, 0x02,
+  0x90, 0xFF,
+];
+const BIOS_SIZE: usize = 0x800;
+const POP: u8 = 0x1C;
+
+pub(super) const TITLE: &str = "Built-in free BIOS (starts cartridges, has no BIOS routines)";
+
+pub(super) fn image() -> Vec<u8> {
+  let mut image = vec![POP; BIOS_SIZE];
+  image[..BOOT.len()].copy_from_slice(BOOT);
+  image
+}
+
+/// Watches for calls into BIOS routines the free BIOS doesn't have. Only while it is the BIOS in use.
+/// A new one is made on every power on and reset, so each boot reports again.
+pub(super) struct RoutineCheck {
+  reported: bool, //Only the first call is reported.
+}
+
+impl RoutineCheck {
+  pub fn new() -> Self {
+    Self { reported: false }
+  }
+
+  /// Called after every instruction. Returns the address of the routine PC0 just jumped to, the first time.
+  pub fn run_cycle(&mut self, board: &fairchild_f8::Board) -> Option<u16> {
+    let pc0 = board.roms[0].pc0;
+    if self.reported || !(BOOT.len()..BIOS_SIZE).contains(&(pc0 as usize)) {
+      return None;
+    }
+    self.reported = true;
+    Some(pc0)
+  }
+}
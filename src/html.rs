@@ -0,0 +1,15 @@
+/// Makes text safe to put in the page, inside elements or quoted attributes.
+pub(super) fn escape(text: &str) -> String {
+  text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn markup_and_quotes() {
+    assert_eq!(escape("<b title=\"a&b\">"), "&lt;b title=&quot;a&amp;b&quot;&gt;");
+    assert_eq!(escape("Dodge' It"), "Dodge' It");
+  }
+}
@@ -1,6 +1,36 @@
 use chips::fairchild_f8;
+use std::collections::VecDeque;
 use wasm_bindgen::prelude::*;
 
+//Autostart times are counted in refresh cycles, which happen every 2 frames.
+const AUTOSTART_DELAY: u32 = 50;  //Give the BIOS time to clear the screen and ask for a game.
+const AUTOSTART_HOLD: u32 = 5;
+const AUTOSTART_GAP: u32 = 15;
+pub(super) const BUTTON_CODES: std::ops::RangeInclusive<u8> = 1..=12; //1 - 4 are the console, 5 - 12 the controller.
+
+/// Which keys of the computer keyboard are the controller. The key maps themselves are in the page.
+#[derive(Clone, Copy, PartialEq)]
+pub(super) enum KeyPreset {
+  Numpad, //Arrows and the numpad, F1 - F4 for the console.
+  Wasd, //WASD, Q/E to twist, J/K to push/pull, 1 - 4 for the console.
+}
+
+impl KeyPreset {
+  pub fn from_param(param: Option<&String>) -> Self {
+    match param.map(|p| p.as_str()) {
+      Some("wasd") => KeyPreset::Wasd,
+      _ => KeyPreset::Numpad,
+    }
+  }
+
+  fn name(&self) -> &'static str {
+    match self {
+      KeyPreset::Numpad => "numpad",
+      KeyPreset::Wasd => "wasd",
+    }
+  }
+}
+
 #[derive(Default)]
 struct PressedButtons {
   console1: Action,
@@ -23,6 +53,8 @@ pub(super) struct Keyboard {
   pending_move_var: wasm_bindgen::JsValue,
   pending_wheel_var: wasm_bindgen::JsValue,
   cycle_count: u8,
+  refresh_count: u32,
+  autostart: VecDeque<(u32, u8, Action)>, //(refresh cycle, button code, press or release)
 }
 
 #[derive(Default, PartialEq)]
@@ -34,7 +66,23 @@ enum Action {
 }
 
 impl Keyboard {
-  pub fn new() -> Self {
+  /// The autostart buttons get pressed one after another, once the BIOS is ready.
+  pub fn new(preset: KeyPreset, autostart: &[u8]) -> Self {
+    let set_key_preset_var = js_sys::Reflect::get(
+      &wasm_bindgen::JsValue::from(web_sys::window().unwrap()),
+      &wasm_bindgen::JsValue::from("setKeyPreset"),
+    ).unwrap();
+    if let Some(set_key_preset) = set_key_preset_var.dyn_ref::<js_sys::Function>() {
+      let _ = set_key_preset.call1(&JsValue::null(), &JsValue::from(preset.name()));
+    }
+
+    let mut autostart_queue = VecDeque::new();
+    for (index, &code) in autostart.iter().enumerate() {
+      let press_cycle = AUTOSTART_DELAY + index as u32 * (AUTOSTART_HOLD + AUTOSTART_GAP);
+      autostart_queue.push_back((press_cycle, code, Action::Keyboard));
+      autostart_queue.push_back((press_cycle + AUTOSTART_HOLD, code, Action::None));
+    }
+
     let pending_button_var = js_sys::Reflect::get(
       &wasm_bindgen::JsValue::from(web_sys::window().unwrap()),
       &wasm_bindgen::JsValue::from("getPendingButton"),
@@ -52,10 +100,12 @@ impl Keyboard {
     
     Self {
       pressed_buttons: Default::default(),
-      pending_button_var: pending_button_var,
-      pending_move_var: pending_move_var,
-      pending_wheel_var: pending_wheel_var,
+      pending_button_var,
+      pending_move_var,
+      pending_wheel_var,
       cycle_count: 0,
+      refresh_count: 0,
+      autostart: autostart_queue,
     }
   }
   
@@ -75,6 +125,12 @@ impl Keyboard {
 
   
   pub fn run_refresh_cycle(&mut self, board: &mut fairchild_f8::Board) {
+    self.refresh_count += 1;
+    while self.autostart.front().is_some_and(|&(cycle, _, _)| cycle <= self.refresh_count) {
+      let (_, code, press_type) = self.autostart.pop_front().unwrap();
+      self.set_button(code, press_type);
+    }
+
     let (x, y) = self.get_movement();
     //Mouse shouldn't interrupt keyboard press
     if self.pressed_buttons.left != Action::Keyboard
@@ -128,25 +184,29 @@ impl Keyboard {
         board.cpu.reset = true;
         self.pressed_buttons = Default::default();  //Since we are reseting, let's reset all buttons just in case...
       } else {
-        match scan_code {
-          1 => self.pressed_buttons.console1 = press_type,
-          2 => self.pressed_buttons.console2 = press_type,
-          3 => self.pressed_buttons.console3 = press_type,
-          4 => self.pressed_buttons.console4 = press_type,
-          5 => self.pressed_buttons.push = press_type, //Left mouse
-          6 => self.pressed_buttons.pull = press_type, //Right mouse
-          7 => self.pressed_buttons.left = press_type,
-          8 => self.pressed_buttons.right = press_type,
-          9 => self.pressed_buttons.forward = press_type,
-          10 => self.pressed_buttons.backward = press_type,
-          11 => self.pressed_buttons.clock = press_type,
-          12 => self.pressed_buttons.anticlock = press_type,
-          255 => (),  //Restart
-          _ => unimplemented!(),
-        }
+        self.set_button(scan_code, press_type);
       }
     }
   }
+
+  fn set_button(&mut self, scan_code: u8, press_type: Action) {
+    match scan_code {
+      1 => self.pressed_buttons.console1 = press_type,
+      2 => self.pressed_buttons.console2 = press_type,
+      3 => self.pressed_buttons.console3 = press_type,
+      4 => self.pressed_buttons.console4 = press_type,
+      5 => self.pressed_buttons.push = press_type, //Left mouse
+      6 => self.pressed_buttons.pull = press_type, //Right mouse
+      7 => self.pressed_buttons.left = press_type,
+      8 => self.pressed_buttons.right = press_type,
+      9 => self.pressed_buttons.forward = press_type,
+      10 => self.pressed_buttons.backward = press_type,
+      11 => self.pressed_buttons.clock = press_type,
+      12 => self.pressed_buttons.anticlock = press_type,
+      255 => (),  //Restart
+      _ => log::warn!(target: "ports", "There is no button with the code {}", scan_code),
+    }
+  }
   
    //Wish there were a way to get an integer directly without needing to go through a float...
   fn get_keypress(&self) -> Option<(u8, Action)> {
@@ -1,14 +1,38 @@
 #![forbid(unsafe_code)]
 
 mod sound;
+mod tone;
 mod video;
 mod keyboard;
 mod side_panel;
+mod sound_panel;
+mod scheduler;
+mod commands;
+mod loader;
+mod archive;
+mod checksum;
+mod rom_db;
+mod chf;
+mod cartridge;
+mod free_bios;
+mod patch;
+mod disassembler;
+mod debugger;
+mod breakpoints;
+mod watchpoints;
+mod config;
+mod save_ram;
+mod download;
+mod trace;
+mod trace_diff;
+mod port_monitor;
+mod memory_editor;
+mod cheats;
+mod html;
+mod state;
 
 use wasm_bindgen::prelude::*;
 
-use chips::fairchild_f8;
-
 #[wasm_bindgen]
 pub async fn run() {
   std::panic::set_hook(Box::new(console_error_panic_hook::hook)); //Panics appear more descriptive in the browser console.
@@ -16,88 +40,441 @@ pub async fn run() {
   let window = web_sys::window().unwrap();
   let location = window.location();
   let search = location.search().unwrap();
-  let params = parse_query_string(&search);
+  let config = config::Config::from_query_string(&search);
+  trace::init(&config.log);
   
   let document = window.document().unwrap();
+  //The log settings in the page start out as the URL has them.
+  for (id, value) in [("log_level", config.log.clone()), ("trace_size", config.trace.to_string())] {
+    if let Some(element) = document.get_element_by_id(id) {
+      let _ = js_sys::Reflect::set(&element, &JsValue::from("value"), &JsValue::from(value));
+    }
+  }
   
-  let bios = if let Some(link) = params.get("bios") {
-    if let Some(element) = document.get_element_by_id("bios") {
-      if let Some(input) = element.dyn_ref::<web_sys::HtmlInputElement>() {
-        input.set_value(link);
+  let mut roms = loader::Roms::default();
+  let mut choices = None;
+  let mut errors = vec![];
+  for (param, link, kind) in [("bios", &config.bios, loader::RomKind::Bios), ("rom", &config.rom, loader::RomKind::Cartridge)] {
+    if let Some(link) = link {
+      if let Some(element) = document.get_element_by_id(param) {
+        if let Some(input) = element.dyn_ref::<web_sys::HtmlInputElement>() {
+          input.set_value(link);
+        }
+      }
+      let inserted = match loader::fetch(&window, link).await {
+        Ok(bytes) => roms.insert(loader::open(kind, bytes), Some(link), &mut choices),
+        Err(error) => Err(error),
+      };
+      if let Err(error) = inserted {
+        errors.push(error);
       }
     }
-    fetch(&window, link).await
-  } else { None };
+  }
+  if let Some(link) = &config.patch {
+    let patched = match loader::fetch(&window, link).await {
+      Ok(bytes) => roms.patch(&bytes),
+      Err(error) => Err(error),
+    };
+    if let Err(error) = patched {
+      errors.push(error);
+    }
+  }
+  if roms.bios.is_none() && roms.cartridge.is_none() {
+    errors.push(loader::LoadError::NoBios);
+  }
 
-  let rom = if let Some(link) = params.get("rom") {
-    if let Some(element) = document.get_element_by_id("rom") {
-      if let Some(input) = element.dyn_ref::<web_sys::HtmlInputElement>() {
-        input.set_value(link);
-      }
+  let (mut board, mut cartridge, mut save_ram) = power_on(&roms);
+  if let Some(link) = &config.state {
+    let loaded = match loader::fetch(&window, link).await {
+      Ok(bytes) => state::load(&bytes, &roms, &mut board, &mut cartridge),
+      Err(error) => Err(error),
+    };
+    if let Err(error) = loaded {
+      errors.push(error);
     }
-    fetch(&window, link).await
-  } else { None };
-    
-  let mut board = fairchild_f8::Board::new(bios, rom);
-  let mut keyboard = keyboard::Keyboard::new();
-  let mut video = video::Video::new();
-  let mut sound = sound::Sound::new();
+  }
+  loader::show_errors(&errors);
+  let mut keyboard = keyboard::Keyboard::new(config.keys, &config.autostart);
+  let mut video = video::Video::new(config.palette, config.scale);
+  let mut sound = sound::Sound::new(Default::default(), config.timing, config.volume);
   let mut side_panel = side_panel::SidePanel::new();
+  let mut sound_panel = sound_panel::SoundPanel::new(config.timing);
+  let mut port_monitor = port_monitor::PortMonitor::new();
+  let mut memory_editor = memory_editor::MemoryEditor::new();
+  let mut cheats = cheats::Cheats::new(&roms);
+  let mut scheduler = scheduler::Scheduler::new(config.pacing, config.timing);
+  let mut debugger = debugger::Debugger::new();
+  let mut trace = trace::Trace::new(config.trace);
+  let mut bios_routines = free_bios::RoutineCheck::new();
+  let commands = commands::Commands::new();
 
   let mut refresh_count = 0;
   //Frame cycle
   loop {
-    //Instruction cycle
-    loop {
-      keyboard.run_cycle(&mut board);
-      let clock_ticks = board.run_cycle() as usize;
-      board.roms[0].print();
-      board.cpu.print();
-      if sound.run_cycle(&board, clock_ticks) {
-        //We filled up the sound buffer. We shouldn't run any more instructions until we get a new buffer. Go to the next frame.
-        break;
+    let frames = if debugger.is_running() {
+      scheduler.wait(&sound).await
+    } else {
+      scheduler.idle().await;
+      0
+    };
+    let mut powered_on = false;
+    let mut edited = false; //While paused, nothing else would redraw what was changed.
+    while let Some(command) = commands.next() {
+      match command {
+        commands::Command::AudioConfig(config) => sound.set_config(config),
+        commands::Command::LoadRom(kind, bytes, name) => {
+          match roms.insert(loader::open(kind, bytes), name.as_deref(), &mut choices) {
+            Ok(changed) => {
+              loader::show_errors(&[]);
+              powered_on |= swap_roms(&roms, &changed, &mut board, &mut cartridge, &mut save_ram, &mut cheats);
+            },
+            Err(error) => loader::show_errors(&[error]),
+          }
+        },
+        commands::Command::LoadLink(kind, link) => {
+          let inserted = match loader::fetch(&window, &link).await {
+            Ok(bytes) => roms.insert(loader::open(kind, bytes), Some(&link), &mut choices),
+            Err(error) => Err(error),
+          };
+          match inserted {
+            Ok(changed) => {
+              loader::show_errors(&[]);
+              powered_on |= swap_roms(&roms, &changed, &mut board, &mut cartridge, &mut save_ram, &mut cheats);
+            },
+            Err(error) => loader::show_errors(&[error]),
+          }
+        },
+        commands::Command::Patch(bytes) => {
+          match roms.patch(&bytes) {
+            Ok(()) => {
+              loader::show_errors(&[]);
+              powered_on |= swap_roms(&roms, &[loader::RomKind::Cartridge], &mut board, &mut cartridge, &mut save_ram, &mut cheats);
+            },
+            Err(error) => loader::show_errors(&[error]),
+          }
+        },
+        commands::Command::PatchLink(link) => {
+          let patched = match loader::fetch(&window, &link).await {
+            Ok(bytes) => roms.patch(&bytes),
+            Err(error) => Err(error),
+          };
+          match patched {
+            Ok(()) => {
+              loader::show_errors(&[]);
+              powered_on |= swap_roms(&roms, &[loader::RomKind::Cartridge], &mut board, &mut cartridge, &mut save_ram, &mut cheats);
+            },
+            Err(error) => loader::show_errors(&[error]),
+          }
+        },
+        commands::Command::CartridgeType(cartridge_type) => {
+          roms.chosen_cartridge_type = cartridge_type;
+          powered_on |= swap_roms(&roms, &[loader::RomKind::Cartridge], &mut board, &mut cartridge, &mut save_ram, &mut cheats);
+        },
+        commands::Command::ChooseArchiveEntry(index) => {
+          if let Some((kind, bytes)) = choices.take().and_then(|choices| choices.choose(index)) {
+            match roms.set(kind, bytes) {
+              Ok(()) => {
+                loader::show_errors(&[]);
+                powered_on |= swap_roms(&roms, &[kind], &mut board, &mut cartridge, &mut save_ram, &mut cheats);
+              },
+              Err(error) => loader::show_errors(&[error]),
+            }
+          }
+        },
+        commands::Command::Eject => {
+          roms.eject();
+          powered_on |= swap_roms(&roms, &[loader::RomKind::Cartridge], &mut board, &mut cartridge, &mut save_ram, &mut cheats);
+          if roms.bios.is_none() {
+            loader::show_errors(&[loader::LoadError::NoBios]);
+          }
+        },
+        commands::Command::Reset => board.cpu.reset = true,
+        commands::Command::PowerCycle => {
+          save_ram.save(&cartridge, &board);
+          (board, cartridge, save_ram) = power_on(&roms);
+          cheats.load(&roms);
+          powered_on = true;
+        },
+        commands::Command::ExportSaveRam => save_ram.export(&cartridge, &board),
+        commands::Command::ImportSaveRam(bytes) => save_ram.import(&mut cartridge, &mut board, &bytes),
+        commands::Command::ClearSaveRam => save_ram.clear(&mut cartridge, &mut board),
+        commands::Command::SaveState => state::save(&roms, &board, &cartridge),
+        commands::Command::LoadState(bytes) => {
+          match state::load(&bytes, &roms, &mut board, &mut cartridge) {
+            Ok(()) => {
+              loader::show_errors(&[]);
+              edited = true;
+            },
+            Err(error) => loader::show_errors(&[error]),
+          }
+        },
+        commands::Command::Pause => debugger.pause(),
+        commands::Command::Resume => debugger.resume(),
+        commands::Command::TogglePause => debugger.toggle_pause(),
+        commands::Command::Step => debugger.step(),
+        commands::Command::StepOver => debugger.step_over(&board),
+        commands::Command::RunToNextFrame => debugger.run_to_next_frame(),
+        commands::Command::FrameAdvance => debugger.frame_advance(scheduler.in_frame()),
+        commands::Command::AddBreakpoint(address, condition, stop_after) => debugger.add_breakpoint(&address, &condition, stop_after),
+        commands::Command::RemoveBreakpoint(index) => debugger.remove_breakpoint(index),
+        commands::Command::AddWatchpoint(space, address, access, value) => debugger.add_watchpoint(&board, &space, &address, &access, &value),
+        commands::Command::RemoveWatchpoint(index) => debugger.remove_watchpoint(index),
+        commands::Command::LogLevel(level) => trace::set_level(&level),
+        commands::Command::LogCategory(category, enabled) => trace::set_category(&category, enabled),
+        commands::Command::TraceSize(size) => trace.set_size(size),
+        commands::Command::DownloadTrace => trace.download(),
+        commands::Command::SetRegister(index, value) => {
+          //Only while paused, so the change is seen before the program runs over it.
+          if !debugger.is_running() && index < board.cpu.regs.len() {
+            board.cpu.regs[index] = value;
+            edited = true;
+          }
+        },
+        commands::Command::ViewMemory(space, address) => {
+          memory_editor.view(space, address);
+          edited = true;
+        },
+        commands::Command::SetMemory(space, address, value) => {
+          if !debugger.is_running() {
+            memory_editor.write(&mut board, &mut cartridge, space, address, value);
+            edited = true;
+          }
+        },
+        commands::Command::AddCheat(name, code) => cheats.add(&name, &code),
+        commands::Command::ToggleCheat(index) => cheats.toggle(index),
+        commands::Command::RemoveCheat(index) => cheats.remove(index),
+        commands::Command::FilterCheats(filter) => {
+          cheats.set_filter(&filter);
+          edited = true;
+        },
+        commands::Command::StartCheatSearch(space) => cheats.start_search(space, &board, &cartridge),
+        commands::Command::CheatSearch(comparison) => cheats.search(comparison, &board, &cartridge),
+        commands::Command::MonitorPorts(enabled) => port_monitor.set_enabled(enabled),
+        commands::Command::ClearPortLog => port_monitor.clear(),
+        commands::Command::CompareTrace(bytes) => {
+          //The trace is off by default, which would make every comparison come out empty.
+          let report = if trace.is_empty() {
+            "Our trace is empty. Set Trace instructions above 0, run up to where the reference log starts, then compare again.".to_string()
+          } else {
+            trace_diff::compare(&trace.text(), &String::from_utf8_lossy(&bytes))
+          };
+          trace_diff::show(&report);
+        },
       }
     }
+    //Whether it was a power cycle or a new BIOS, the trace starts over with the new board.
+    if powered_on {
+      trace.reset();
+      debugger.power_on(&board);
+      bios_routines = free_bios::RoutineCheck::new();
+    }
+    //While paused, only what the debugger asked for runs.
+    let frames = if debugger.is_running() { frames } else { debugger.frames_to_run() };
+    for _ in 0..frames {
+      scheduler.start_frame();
+      //Instruction cycle
+      let (frame_done, stop) = loop {
+        keyboard.run_cycle(&mut board);
+        trace.run_cycle(&board);
+        let port_write = watchpoints::port_write(&board);
+        //The Reset command, the console's reset key and a new cartridge all reset here, and the free BIOS boots again.
+        if board.cpu.reset {
+          bios_routines = free_bios::RoutineCheck::new();
+        }
+        let clock_ticks = board.run_cycle() as usize;
+        trace.add_ticks(clock_ticks);
+        cartridge.run_cycle(&mut board, clock_ticks, port_write);
+        port_monitor.run_cycle(&board, clock_ticks);
+        if let Some(tone_event) = sound.run_cycle(&board, clock_ticks, port_write) {
+          log::debug!(target: "sound", "Tone {} at tick {}", tone_event.tone, tone_event.tick);
+          sound_panel.log_tone(tone_event);
+        }
+        let frame_done = scheduler.run_cycle(clock_ticks);
+        let mut stop = debugger.run_cycle(&board, frame_done);
+        if roms.bios.is_none() {
+          if let Some(address) = bios_routines.run_cycle(&board) {
+            loader::show_errors(&[loader::LoadError::NoBiosRoutine(address)]);
+            debugger.stop(&board, format!("the free BIOS has no routine at {:04X}", address));
+            stop = true;
+          }
+        }
+        if frame_done || stop {
+          //Either we ran one frame worth of clock ticks, or the debugger stopped us in the middle of the frame.
+          break (frame_done, stop);
+        }
+      };
+      if frame_done {
+        sound.run_refresh_cycle();
+        port_monitor.run_refresh_cycle();
+        cheats.run_refresh_cycle(&mut board, &mut cartridge);
+        save_ram.run_refresh_cycle(&cartridge, &board, config.timing);
 
-    refresh_count += 1;
-    video.run_refresh_cycle(&board);
+        refresh_count += 1;
+        if refresh_count % 2 == 0 {
+          keyboard.run_refresh_cycle(&mut board);
+          refresh_count = 0;
+        }
+      }
+      if stop {
+        break;
+      }
+    }
 
-    if refresh_count % 2 == 0 {
-      keyboard.run_refresh_cycle(&mut board);
+    if frames > 0 || edited {
+      video.run_refresh_cycle(&board);
       side_panel.print_memory(&board);
-      refresh_count = 0;
+      side_panel.print_cpu(&board);
+      side_panel.print_audio_status(sound.latency(), sound.underruns());
+      sound_panel.print(sound.total_ticks());
+      port_monitor.print();
+      memory_editor.print(&board, &cartridge);
     }
-    
-    sound.run_refresh_cycle().await;  //Wait till the previous sound was played.
+    debugger.print(&board);
+    cheats.print(&board, &cartridge);
   }
 
 }
 
-fn parse_query_string(query_string: &str) -> std::collections::HashMap<String, String> {
-  let mut result = std::collections::HashMap::new();
+/// Builds the board for the loaded ROMs, with the cartridge RAM as it was last saved.
+fn power_on(roms: &loader::Roms) -> (chips::fairchild_f8::Board, cartridge::Cartridge, save_ram::SaveRam) {
+  let (mut board, mut cartridge) = roms.build();
+  let save_ram = save_ram::SaveRam::new(roms, &mut cartridge, &mut board);
+  (board, cartridge, save_ram)
+}
 
-  for pair in query_string.trim_start_matches('?').split('&') {
-    let mut key_value = pair.split('=');
-    if let (Some(key), Some(value)) = (key_value.next(), key_value.next()) {
-      result.insert(key.to_string(), value.to_string());
-    }
+/// A new BIOS needs the power turned off and on. A new cartridge goes into the running console, followed by a reset,
+/// like pressing Reset after swapping cartridges on the real thing. Either way, the RAM of the old cartridge is saved first.
+/// Returns true if it is a new board.
+fn swap_roms(
+  roms: &loader::Roms,
+  changed: &[loader::RomKind],
+  board: &mut chips::fairchild_f8::Board,
+  cartridge: &mut cartridge::Cartridge,
+  save_ram: &mut save_ram::SaveRam,
+  cheats: &mut cheats::Cheats,
+) -> bool {
+  if changed.is_empty() {
+    return false;
+  }
+  save_ram.save(cartridge, board);
+  cheats.load(roms);
+  if changed.contains(&loader::RomKind::Bios) {
+    (*board, *cartridge, *save_ram) = power_on(roms);
+    return true;
   }
+  *cartridge = roms.swap_cartridge(board);
+  *save_ram = save_ram::SaveRam::new(roms, cartridge, board);
+  board.cpu.reset = true;
+  false
+}
 
-  result
+/// Stops the emulation after the instruction being run.
+#[wasm_bindgen]
+pub fn pause() {
+  commands::queue(commands::Command::Pause);
 }
 
-async fn fetch(window: &web_sys::Window, link: &str) -> Option<Vec<u8>> {
-  if let Ok(request) = web_sys::Request::new_with_str(link) {
-    if let Ok(resp_value) = wasm_bindgen_futures::JsFuture::from(window.fetch_with_request(&request)).await {
-      if let Ok(resp) = resp_value.dyn_into::<web_sys::Response>() {
-        if let Ok(buffer_future) = resp.array_buffer() {
-          if let Ok(buffer) = wasm_bindgen_futures::JsFuture::from(buffer_future).await {
-            let uint8_array = js_sys::Uint8Array::new(&buffer);
-            return Some(uint8_array.to_vec());
-          }
-        }
-      }
-    }
-  }
-  None
-}
\ No newline at end of file
+#[wasm_bindgen]
+pub fn resume() {
+  commands::queue(commands::Command::Resume);
+}
+
+/// Runs one instruction, then stays paused.
+#[wasm_bindgen]
+pub fn step() {
+  commands::queue(commands::Command::Step);
+}
+
+/// Like step, but a subroutine call runs until it returns.
+#[wasm_bindgen]
+pub fn step_over() {
+  commands::queue(commands::Command::StepOver);
+}
+
+/// Runs until the frame in progress is finished, then pauses.
+#[wasm_bindgen]
+pub fn run_to_next_frame() {
+  commands::queue(commands::Command::RunToNextFrame);
+}
+
+/// Runs one whole frame, then pauses.
+#[wasm_bindgen]
+pub fn frame_advance() {
+  commands::queue(commands::Command::FrameAdvance);
+}
+
+/// Stops when PC0 reaches the address and the condition holds, like "A == 0x3F". An empty condition always holds.
+/// With stop_after above 1, it only stops on every that many hits.
+#[wasm_bindgen]
+pub fn add_breakpoint(address: u16, condition: String, stop_after: u32) {
+  commands::queue(commands::Command::AddBreakpoint(format!("{}", address), condition, stop_after));
+}
+
+#[wasm_bindgen]
+pub fn remove_breakpoint(index: usize) {
+  commands::queue(commands::Command::RemoveBreakpoint(index));
+}
+
+/// Stops right after an instruction reads or writes a location.
+/// space is "scratchpad", "memory" (cartridge RAM included), "port" or "vram". VRAM addresses are pixels, like "10,20".
+/// access is "r", "w" or "rw". value is a byte to wait for, or empty for any.
+#[wasm_bindgen]
+pub fn add_watchpoint(space: String, address: String, access: String, value: String) {
+  commands::queue(commands::Command::AddWatchpoint(space, address, access, value));
+}
+
+#[wasm_bindgen]
+pub fn remove_watchpoint(index: usize) {
+  commands::queue(commands::Command::RemoveWatchpoint(index));
+}
+
+/// Freezes bytes to a value every frame, like "R10=09,2A00=FF". See the README for the format.
+#[wasm_bindgen]
+pub fn add_cheat(name: String, code: String) {
+  commands::queue(commands::Command::AddCheat(name, code));
+}
+
+/// Saves cartridge RAM which changed since the last periodic save. The page calls this as it closes.
+#[wasm_bindgen]
+pub fn flush_save_ram() {
+  save_ram::flush();
+}
+
+/// The cartridge RAM as a file, the same as the export in the page. Empty if the cartridge has no RAM.
+/// It's as of the last frame, so it can be kept by a script without the page.
+#[wasm_bindgen]
+pub fn export_save_ram() -> Vec<u8> {
+  save_ram::file()
+}
+
+/// Loads a file from export_save_ram into the cartridge RAM, and saves it.
+#[wasm_bindgen]
+pub fn import_save_ram(bytes: &[u8]) {
+  commands::queue(commands::Command::ImportSaveRam(bytes.to_vec()));
+}
+
+/// Forgets the saved RAM of the cartridge and empties it.
+#[wasm_bindgen]
+pub fn clear_save_ram() {
+  commands::queue(commands::Command::ClearSaveRam);
+}
+
+/// Disassembles a whole ROM, one instruction per line. origin is where it is loaded: 0 for the BIOS, 0x0800 for cartridges.
+/// Needs no running console, so it works for dumping ROMs from a script.
+#[wasm_bindgen]
+pub fn disassemble(bytes: &[u8], origin: u16) -> String {
+  disassembler::disassemble(bytes, origin)
+}
+
+/// Compares two traces in the format of the trace download, or a reference log from another emulator.
+/// Says where they first differ, with the lines before.
+#[wasm_bindgen]
+pub fn compare_traces(ours: String, reference: String) -> String {
+  trace_diff::compare(&ours, &reference)
+}
+
+/// The compatibility table of the README, generated from the ROM database.
+#[wasm_bindgen]
+pub fn compatibility_table() -> String {
+  rom_db::compatibility_table()
+}
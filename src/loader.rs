@@ -0,0 +1,403 @@
+use wasm_bindgen::prelude::*;
+
+use chips::fairchild_f8;
+use super::archive;
+use super::rom_db;
+use super::chf;
+use super::cartridge;
+use super::free_bios;
+use super::patch;
+use super::checksum;
+use super::html;
+
+#[derive(Clone, Copy, PartialEq)]
+pub(super) enum RomKind {
+  Bios,
+  Cartridge,
+}
+
+impl RomKind {
+  /// The same names as the URL parameters.
+  pub fn from_name(name: &str) -> Option<Self> {
+    match name {
+      "bios" => Some(RomKind::Bios),
+      "rom" => Some(RomKind::Cartridge),
+      _ => None,
+    }
+  }
+
+  fn name(&self) -> &'static str {
+    match self {
+      RomKind::Bios => "BIOS",
+      RomKind::Cartridge => "cartridge",
+    }
+  }
+}
+
+const BIOS_SIZES: [usize; 2] = [0x400, 0x800]; //One chip, or both chips in one file.
+const MAX_CARTRIDGE_SIZE: usize = 0x100000; //Bigger than any cartridge, multicarts included.
+const CARTRIDGE_HEADER: u8 = 0x55;  //The BIOS only starts a cartridge which has this as its first byte.
+
+/// Why a ROM couldn't be loaded. Shown in the page, so the user knows why nothing runs.
+pub(super) enum LoadError {
+  BadLink(String),
+  Network(String),  //The browser doesn't tell us why. Usually the server doesn't allow cross-origin requests (CORS).
+  HttpStatus(String, u16, String),
+  NotBinary(String, String),  //The server sent a web page, often an error page or a page about the file.
+  Empty(RomKind),
+  EmptyArchive,
+  BrokenChf,
+  WrongKind(RomKind, &'static str), //The database knows this ROM as the other kind.
+  WrongBiosSize(usize),
+  CartridgeTooLarge(usize),
+  NoCartridgeHeader(u8),
+  PatchAsRom,
+  NotAPatch,
+  BrokenPatch,
+  PatchForOtherRom(u32, u32), //The CRC-32 the patch wants, and the one of the cartridge.
+  NothingToPatch,
+  NoBios, //Nothing to run at all. The free BIOS alone only waits for a cartridge.
+  NoBiosRoutine(u16), //The free BIOS is missing the routine at this address.
+  BrokenState,
+  StateForOtherRom(u32, u32), //The CRC-32 of the cartridge the state was saved with, and the one of the cartridge.
+}
+
+impl std::fmt::Display for LoadError {
+  fn fmt(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+    match self {
+      LoadError::BadLink(link) => write!(formatter, "\"{}\" is not a valid link.", link),
+      LoadError::Network(link) => write!(formatter, "Could not download {}. The server may not allow downloads from other sites (CORS), or it can't be reached.", link),
+      LoadError::HttpStatus(link, status, status_text) => write!(formatter, "Could not download {}. The server answered {} {}.", link, status, status_text),
+      LoadError::NotBinary(link, content_type) => write!(formatter, "{} is a web page ({}), not a ROM. Link to the file itself.", link, content_type),
+      LoadError::Empty(kind) => write!(formatter, "The {} file is empty.", kind.name()),
+      LoadError::EmptyArchive => write!(formatter, "The archive has no ROM in it."),
+      LoadError::BrokenChf => write!(formatter, "The .chf file is damaged."),
+      LoadError::WrongKind(kind, title) => write!(formatter, "This is {}, which is not a {}.", title, kind.name()),
+      LoadError::WrongBiosSize(size) => write!(formatter, "The BIOS should be 1024 or 2048 bytes, but this file is {} bytes.", size),
+      LoadError::CartridgeTooLarge(size) => write!(formatter, "The cartridge is {} bytes, bigger than any cartridge.", size),
+      LoadError::PatchAsRom => write!(formatter, "This is a patch, not a ROM. Load the cartridge first, then apply the patch to it."),
+      LoadError::NotAPatch => write!(formatter, "This is not an IPS or BPS patch."),
+      LoadError::BrokenPatch => write!(formatter, "The patch is damaged. Its checksums don't match."),
+      LoadError::PatchForOtherRom(expected, actual) => write!(formatter, "The patch is for the ROM with CRC32 {:08X}, but the cartridge is {:08X}.", expected, actual),
+      LoadError::NothingToPatch => write!(formatter, "There is no cartridge to patch."),
+      LoadError::NoCartridgeHeader(byte) => write!(formatter, "The cartridge starts with {:02X} instead of {:02X}, so the BIOS won't start it. It may not be a Channel F ROM.", byte, CARTRIDGE_HEADER),
+      LoadError::NoBios => write!(formatter, "No BIOS or cartridge is loaded. Give a link to one above, or pick a local file."),
+      LoadError::NoBiosRoutine(address) => write!(formatter, "The cartridge called the BIOS routine at {:04X}, which the free BIOS doesn't have. Load the real BIOS to play it.", address),
+      LoadError::BrokenState => write!(formatter, "This is not a save state, or it is damaged."),
+      LoadError::StateForOtherRom(expected, actual) => write!(formatter, "The save state is for the cartridge with CRC32 {:08X}, but the cartridge is {:08X}.", expected, actual),
+    }
+  }
+}
+
+/// The ROM images the board is built from. We hold onto them so one can be swapped while keeping the other.
+#[derive(Default)]
+pub(super) struct Roms {
+  pub bios: Option<Vec<u8>>,
+  pub cartridge: Option<Vec<u8>>,
+  pub cartridge_type: Option<rom_db::CartridgeType>,  //Set when the file says what hardware the cartridge has.
+  pub chosen_cartridge_type: Option<rom_db::CartridgeType>,  //Set when the user picked the hardware. Wins over everything else.
+  pub cartridge_title: Option<String>,
+  pub cartridge_name: Option<String>, //The file or link the cartridge came from.
+  chf_file: Option<Vec<u8>>,  //The .chf file the cartridge was flattened from, as a patch may be made for it.
+}
+
+impl Roms {
+  /// A .chf cartridge is flattened into a raw image, the same as a .bin file. Nothing changes if the ROM is no good.
+  pub fn set(&mut self, kind: RomKind, bytes: Vec<u8>) -> Result<(), LoadError> {
+    validate(kind, &bytes)?;
+    match kind {
+      RomKind::Bios => self.bios = Some(bytes),
+      RomKind::Cartridge => {
+        self.cartridge_type = None;
+        self.cartridge_title = None;
+        self.cartridge_name = None;
+        self.chf_file = None;
+        match chf::parse(&bytes) {
+          Some(chf) => {
+            self.cartridge_type = match chf.hardware_type {
+              chf::HardwareType::Videocart => Some(rom_db::CartridgeType::Rom),
+              chf::HardwareType::Sram2102 => Some(rom_db::CartridgeType::Sram2102),
+              chf::HardwareType::Schach => Some(rom_db::CartridgeType::Schach),
+              chf::HardwareType::Multicart => Some(rom_db::CartridgeType::Multicart),
+              chf::HardwareType::Smi3853 => Some(rom_db::CartridgeType::Smi3853),
+              chf::HardwareType::Unknown(_) => None,
+            };
+            if !chf.title.is_empty() {
+              self.cartridge_title = Some(chf.title.clone());
+            }
+            self.cartridge = Some(if chf.hardware_type == chf::HardwareType::Multicart {
+              chf.banked_image(cartridge::MULTICART_BANK_SIZE)
+            } else {
+              chf.rom_image(0)
+            });
+            self.chf_file = Some(bytes);
+          },
+          None => self.cartridge = Some(bytes),
+        }
+      },
+    }
+    Ok(())
+  }
+
+  /// Takes in what was opened. If the user has to pick a file from an archive, the choices are shown and stored.
+  /// Returns which images changed. If one of the ROMs is no good, none of them are taken.
+  /// The name of the file or link is kept for a cartridge, to recognize it by if the hash is unknown.
+  pub fn insert(&mut self, opened: Result<Opened, LoadError>, name: Option<&str>, choices: &mut Option<Choices>) -> Result<Vec<RomKind>, LoadError> {
+    let opened = opened?;
+    for (kind, bytes) in &opened.roms {
+      validate(*kind, bytes)?;
+    }
+    //Choices from an earlier archive are gone once something else is loaded.
+    match opened.choices {
+      Some(new_choices) => {
+        new_choices.show();
+        *choices = Some(new_choices);
+      },
+      None => {
+        if choices.take().is_some() {
+          Choices::hide();
+        }
+      },
+    }
+    let mut changed = vec![];
+    for (kind, bytes) in opened.roms {
+      self.set(kind, bytes)?;
+      if kind == RomKind::Cartridge {
+        self.cartridge_name = name.map(str::to_string);
+      }
+      changed.push(kind);
+    }
+    Ok(changed)
+  }
+
+  /// Patches the cartridge. Its hardware and title stay the same.
+  /// A BPS patch for the .chf file is applied to the file, which is then flattened again. Anything else patches the image.
+  pub fn patch(&mut self, patch: &[u8]) -> Result<(), LoadError> {
+    let cartridge = self.cartridge.as_deref().ok_or(LoadError::NothingToPatch)?;
+    if let Some(chf_file) = &self.chf_file {
+      if patch::source_crc(patch) == Some(checksum::crc32(chf_file)) {
+        let patched = patch::apply(chf_file, patch)?;
+        let name = self.cartridge_name.take();
+        self.set(RomKind::Cartridge, patched)?;
+        self.cartridge_name = name;
+        return Ok(());
+      }
+    }
+    let patched = patch::apply(cartridge, patch)?;
+    validate(RomKind::Cartridge, &patched)?;
+    self.cartridge = Some(patched);
+    self.chf_file = None;
+    Ok(())
+  }
+
+  pub fn eject(&mut self) {
+    self.cartridge = None;
+    self.cartridge_type = None;
+    self.cartridge_title = None;
+    self.cartridge_name = None;
+    self.chf_file = None;
+  }
+
+  /// The user's choice, then what the file says, then what the ROM database says. Otherwise it's just ROM.
+  pub fn resolved_cartridge_type(&self) -> rom_db::CartridgeType {
+    self.chosen_cartridge_type
+      .or(self.cartridge_type)
+      .or_else(|| rom_db::cartridge_info(self).map(|info| info.cartridge_type))
+      .unwrap_or(rom_db::CartridgeType::Rom)
+  }
+
+  /// Builds the board with the cartridge hardware plugged in. Also shows in the page which ROMs these are.
+  /// Without a BIOS, the free one is used, so cartridges still start.
+  pub fn build(&self) -> (fairchild_f8::Board, cartridge::Cartridge) {
+    rom_db::print_rom_info(self);
+    let bios = self.bios.clone().unwrap_or_else(free_bios::image);
+    let cartridge = cartridge::Cartridge::new(self.resolved_cartridge_type(), &bios, self.cartridge.as_deref());
+    let board = fairchild_f8::Board::new(Some(bios), cartridge.board_image(self.cartridge.as_deref()));
+    (board, cartridge)
+  }
+
+  /// Puts the cartridge into the running board, leaving the CPU, scratchpad, VRAM and ports as they are.
+  pub fn swap_cartridge(&self, board: &mut fairchild_f8::Board) -> cartridge::Cartridge {
+    let (new_board, cartridge) = self.build();
+    cartridge::carry_over(board, new_board);
+    cartridge
+  }
+}
+
+/// What came out of a file, after unpacking it if it was an archive.
+#[derive(Default)]
+pub(super) struct Opened {
+  pub roms: Vec<(RomKind, Vec<u8>)>,
+  pub choices: Option<Choices>, //The archive has several files which could be what the user wanted.
+}
+
+pub(super) struct Choices {
+  kind: RomKind,
+  entries: Vec<archive::Entry>,
+}
+
+/// Unpacks archives and picks the BIOS and cartridge out of them.
+/// A full ROM set can have both the BIOS and a cartridge, so both get loaded no matter which one the user asked for.
+pub(super) fn open(kind: RomKind, bytes: Vec<u8>) -> Result<Opened, LoadError> {
+  let entries = match archive::unpack(&bytes) {
+    Some(entries) => entries,
+    None => return Ok(Opened { roms: vec![(kind, bytes)], choices: None }),
+  };
+  let mut entries: Vec<archive::Entry> = entries.into_iter().filter(|entry| is_rom_name(&entry.name)).collect();
+  if entries.is_empty() {
+    return Err(LoadError::EmptyArchive);
+  }
+
+  let mut opened = Opened::default();
+  let bios_count = entries.iter().filter(|entry| guess_kind(entry) == RomKind::Bios).count();
+  let cartridge_count = entries.len() - bios_count;
+  for (other_kind, count) in [(RomKind::Bios, bios_count), (RomKind::Cartridge, cartridge_count)] {
+    if other_kind != kind && count == 1 {
+      let index = entries.iter().position(|entry| guess_kind(entry) == other_kind).unwrap();
+      opened.roms.push((other_kind, entries.remove(index).bytes));
+    }
+  }
+
+  let mut candidates: Vec<archive::Entry> = entries.into_iter().filter(|entry| guess_kind(entry) == kind).collect();
+  //If only one of the candidates is in the database, that's the one.
+  let known: Vec<usize> = (0..candidates.len()).filter(|&index| rom_db::identify(&candidates[index].bytes).info.is_some()).collect();
+  if candidates.len() > 1 && known.len() == 1 {
+    opened.roms.push((kind, candidates.swap_remove(known[0]).bytes));
+  } else if candidates.len() == 1 {
+    opened.roms.push((kind, candidates.remove(0).bytes));
+  } else if candidates.len() > 1 {
+    opened.choices = Some(Choices { kind, entries: candidates });
+  }
+  Ok(opened)
+}
+
+/// Catches the usual mistakes: a file that isn't a ROM, the BIOS and cartridge swapped, or a bad dump.
+fn validate(kind: RomKind, bytes: &[u8]) -> Result<(), LoadError> {
+  if bytes.is_empty() {
+    return Err(LoadError::Empty(kind));
+  }
+  if patch::is_patch(bytes) {
+    return Err(LoadError::PatchAsRom);
+  }
+  if let Some(info) = rom_db::identify(bytes).info {
+    return if info.kind == kind { Ok(()) } else { Err(LoadError::WrongKind(kind, info.title)) };
+  }
+  match kind {
+    RomKind::Bios => {
+      //The two chips of a BIOS are identified one at a time.
+      if let Some(info) = rom_db::identify_bios(bytes).iter().find_map(|identified| identified.info) {
+        if info.kind == RomKind::Bios {
+          return Ok(());
+        }
+      }
+      if !BIOS_SIZES.contains(&bytes.len()) {
+        return Err(LoadError::WrongBiosSize(bytes.len()));
+      }
+    },
+    RomKind::Cartridge => {
+      let image = if chf::is_chf(bytes) {
+        let chf = chf::parse(bytes).ok_or(LoadError::BrokenChf)?;
+        chf.rom_image(0)
+      } else {
+        bytes.to_vec()
+      };
+      if image.is_empty() {
+        return Err(LoadError::Empty(kind));
+      }
+      if image.len() > MAX_CARTRIDGE_SIZE {
+        return Err(LoadError::CartridgeTooLarge(image.len()));
+      }
+      if image[0] != CARTRIDGE_HEADER {
+        return Err(LoadError::NoCartridgeHeader(image[0]));
+      }
+    },
+  }
+  Ok(())
+}
+
+/// Shows what went wrong above the screen. An empty list hides the message.
+pub(super) fn show_errors(errors: &[LoadError]) {
+  for error in errors {
+    log::warn!(target: "loader", "{}", error);
+  }
+  let document = web_sys::window().unwrap().document().unwrap();
+  if let Some(element) = document.get_element_by_id("load_error") {
+    let lines: Vec<String> = errors.iter().map(|error| error.to_string()).collect();
+    element.set_text_content(Some(&lines.join("\n")));
+    let _ = element.set_attribute("style", if errors.is_empty() { "display:none" } else { "" });
+  }
+}
+
+fn is_rom_name(name: &str) -> bool {
+  let name = name.to_lowercase();
+  ![".txt", ".nfo", ".diz", ".jpg", ".png", ".pdf", ".htm", ".html", ".xml", ".md"].iter().any(|extension| name.ends_with(extension))
+}
+
+/// By hash first, then by name.
+fn guess_kind(entry: &archive::Entry) -> RomKind {
+  if let Some(info) = rom_db::identify(&entry.bytes).info {
+    return info.kind;
+  }
+  if chf::is_chf(&entry.bytes) {
+    return RomKind::Cartridge;
+  }
+  let name = entry.name.to_lowercase();
+  if name.contains("bios") || name.contains("sl31253") || name.contains("sl31254") {
+    RomKind::Bios
+  } else {
+    RomKind::Cartridge
+  }
+}
+
+impl Choices {
+  fn hide() {
+    let document = web_sys::window().unwrap().document().unwrap();
+    if let Some(picker) = document.get_element_by_id("archive_picker") {
+      let _ = picker.set_attribute("style", "display:none");
+    }
+    if let Some(select) = document.get_element_by_id("archive_entries") {
+      select.set_inner_html("");
+    }
+  }
+
+  /// Lists the files of the archive in the page, so the user can pick one.
+  pub fn show(&self) {
+    let document = web_sys::window().unwrap().document().unwrap();
+    if let (Some(picker), Some(select)) = (document.get_element_by_id("archive_picker"), document.get_element_by_id("archive_entries")) {
+      let mut html = String::new();
+      for (index, entry) in self.entries.iter().enumerate() {
+        html.push_str(&format!("<option value=\"{}\">{} ({} bytes)</option>", index, html::escape(&entry.name), entry.bytes.len()));
+      }
+      select.set_inner_html(&html);
+      let _ = picker.set_attribute("style", "");
+    }
+  }
+
+  /// The user picked a file from the archive.
+  pub fn choose(mut self, index: usize) -> Option<(RomKind, Vec<u8>)> {
+    Self::hide();
+    if index < self.entries.len() {
+      Some((self.kind, self.entries.swap_remove(index).bytes))
+    } else {
+      None
+    }
+  }
+}
+
+pub(super) async fn fetch(window: &web_sys::Window, link: &str) -> Result<Vec<u8>, LoadError> {
+  let request = web_sys::Request::new_with_str(link).map_err(|_| LoadError::BadLink(link.to_string()))?;
+  //fetch() only fails for network errors. A CORS refusal looks the same as an unreachable server.
+  let resp_value = wasm_bindgen_futures::JsFuture::from(window.fetch_with_request(&request)).await.map_err(|_| LoadError::Network(link.to_string()))?;
+  let resp: web_sys::Response = resp_value.dyn_into().map_err(|_| LoadError::Network(link.to_string()))?;
+  if !resp.ok() {
+    return Err(LoadError::HttpStatus(link.to_string(), resp.status(), resp.status_text()));
+  }
+  if let Ok(Some(content_type)) = resp.headers().get("content-type") {
+    if content_type.starts_with("text/html") {
+      return Err(LoadError::NotBinary(link.to_string(), content_type));
+    }
+  }
+  let buffer_future = resp.array_buffer().map_err(|_| LoadError::Network(link.to_string()))?;
+  let buffer = wasm_bindgen_futures::JsFuture::from(buffer_future).await.map_err(|_| LoadError::Network(link.to_string()))?;
+  Ok(js_sys::Uint8Array::new(&buffer).to_vec())
+}
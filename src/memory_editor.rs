@@ -0,0 +1,110 @@
+use chips::fairchild_f8;
+use super::cartridge;
+use super::video;
+
+// Hex view of one page of memory at a time. Bytes of cartridge RAM can be clicked and changed while paused.
+//
+// Memory is what the CPU sees through DC0: the BIOS and the cartridge ROM.
+// VRAM shows one pixel per cell, the two color bits as 0 - 3, 128 to a row of the screen.
+// Both are only shown, since the chips Board has no way to write them from outside.
+// Cartridge RAM is the RAM a battery would keep, as it is saved. The 2102 bits are packed 8 to a byte.
+
+const ROW_SIZE: usize = 16;
+const PAGE_SIZE: usize = 0x100;
+const VRAM_WIDTH: usize = 128;
+const VRAM_SIZE: usize = 128 * 64;
+
+#[derive(Clone, Copy, PartialEq)]
+pub(super) enum Space {
+  Memory,
+  Vram,
+  CartridgeRam,
+}
+
+impl Space {
+  pub fn from_name(name: &str) -> Option<Self> {
+    match name {
+      "memory" => Some(Space::Memory),
+      "vram" => Some(Space::Vram),
+      "cart_ram" => Some(Space::CartridgeRam),
+      _ => None,
+    }
+  }
+}
+
+pub(super) struct MemoryEditor {
+  space: Space,
+  start: usize,
+  element: Option<web_sys::Element>,
+  html: String,
+}
+
+impl MemoryEditor {
+  pub fn new() -> Self {
+    let document = web_sys::window().unwrap().document().unwrap();
+    Self {
+      space: Space::Memory,
+      start: 0,
+      element: document.get_element_by_id("memory_view"),
+      html: String::new(),
+    }
+  }
+
+  /// Shows the page the address is in.
+  pub fn view(&mut self, space: Space, address: usize) {
+    self.space = space;
+    self.start = address - address % PAGE_SIZE;
+  }
+
+  /// Only cartridge RAM can be written.
+  pub fn write(&mut self, board: &mut fairchild_f8::Board, cartridge: &mut cartridge::Cartridge, space: Space, address: usize, value: u8) {
+    if space == Space::CartridgeRam {
+      if let Some(mut ram) = cartridge.save_ram(board) {
+        if let Some(byte) = ram.get_mut(address) {
+          *byte = value;
+          cartridge.load_ram(board, &ram);
+        }
+      }
+    }
+  }
+
+  /// Does nothing while the view is hidden, as it is on small screens.
+  pub fn print(&mut self, board: &fairchild_f8::Board, cartridge: &cartridge::Cartridge) {
+    if self.element.as_ref().is_none_or(|element| element.client_width() == 0) {
+      return;
+    }
+    let ram = if self.space == Space::CartridgeRam { cartridge.save_ram(board) } else { None };
+    let size = match self.space {
+      Space::Memory => 0x10000,
+      Space::Vram => VRAM_SIZE,
+      Space::CartridgeRam => ram.as_ref().map_or(0, |ram| ram.len()),
+    };
+    let read = |address: usize| match self.space {
+      Space::Memory => board.read_memory(address as u16),
+      Space::Vram => video::pixel(board, address % VRAM_WIDTH, address / VRAM_WIDTH),
+      Space::CartridgeRam => ram.as_ref().and_then(|ram| ram.get(address).copied()).unwrap_or(0),
+    };
+
+    let mut html = String::new();
+    if size == 0 {
+      html.push_str("<tr><td>This cartridge has no RAM</td></tr>");
+    }
+    for row in (self.start..(self.start + PAGE_SIZE).min(size)).step_by(ROW_SIZE) {
+      html.push_str(&format!("<tr><th>{:04X}</th>", row));
+      for address in row..(row + ROW_SIZE).min(size) {
+        if self.space == Space::CartridgeRam {
+          html.push_str(&format!("<td data-address=\"{}\">{:02X}</td>", address, read(address)));
+        } else {
+          html.push_str(&format!("<td>{:02X}</td>", read(address)));
+        }
+      }
+      html.push_str("</tr>");
+    }
+    if self.html != html {
+      if let Some(element) = &self.element {
+        element.set_inner_html(&html);
+      }
+      self.html = html;
+    }
+  }
+}
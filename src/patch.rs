@@ -0,0 +1,320 @@
+use super::checksum;
+use super::loader::LoadError;
+
+// Translations, hacks and bug fixes are shared as patches, so the ROM itself doesn't have to be.
+//
+// IPS is a list of (offset, bytes) records. It has no checksums, so it patches any ROM, right or wrong.
+// BPS copies runs of bytes from the original ROM, from the patch or from what it already wrote. It ends with
+// the CRC-32 of the original ROM, of the patched ROM and of the patch itself, which we all check.
+
+const MAX_SIZE: usize = 0x1000000;  //The most IPS can reach with its 3 byte offsets. Far more than any cartridge.
+
+pub(super) fn is_patch(bytes: &[u8]) -> bool {
+  bytes.starts_with(b"PATCH") || bytes.starts_with(b"BPS1")
+}
+
+pub(super) fn apply(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, LoadError> {
+  if patch.starts_with(b"PATCH") {
+    apply_ips(rom, patch).ok_or(LoadError::BrokenPatch)
+  } else if patch.starts_with(b"BPS1") {
+    apply_bps(rom, patch)
+  } else {
+    Err(LoadError::NotAPatch)
+  }
+}
+
+/// The CRC-32 of the ROM a BPS patch was made for. IPS patches don't say.
+pub(super) fn source_crc(patch: &[u8]) -> Option<u32> {
+  if !patch.starts_with(b"BPS1") {
+    return None;
+  }
+  read_u32_le(patch, patch.len().checked_sub(12)?)
+}
+
+fn apply_ips(rom: &[u8], patch: &[u8]) -> Option<Vec<u8>> {
+  let mut image = rom.to_vec();
+  let mut offset = 5;
+  loop {
+    let record = patch.get(offset..offset + 3)?;
+    if record == b"EOF" {
+      offset += 3;
+      break;
+    }
+    let address = read_u24(patch, offset)?;
+    let size = read_u16(patch, offset + 3)? as usize;
+    offset += 5;
+    let (size, data) = if size == 0 {
+      //Run length encoded: a count, then the byte to repeat.
+      let count = read_u16(patch, offset)? as usize;
+      let value = *patch.get(offset + 2)?;
+      offset += 3;
+      (count, vec![value; count])
+    } else {
+      let data = patch.get(offset..offset + size)?.to_vec();
+      offset += size;
+      (size, data)
+    };
+    if image.len() < address + size {
+      image.resize(address + size, 0);
+    }
+    image[address..address + size].copy_from_slice(&data);
+  }
+  //Some patches end with the size to cut the ROM down to.
+  if let Some(size) = read_u24(patch, offset) {
+    if size <= MAX_SIZE {
+      image.truncate(size);
+    }
+  }
+  Some(image)
+}
+
+fn apply_bps(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, LoadError> {
+  if patch.len() < 4 + 12 {
+    return Err(LoadError::BrokenPatch);
+  }
+  let footer = patch.len() - 12;
+  let source_crc = read_u32_le(patch, footer).ok_or(LoadError::BrokenPatch)?;
+  let target_crc = read_u32_le(patch, footer + 4).ok_or(LoadError::BrokenPatch)?;
+  let patch_crc = read_u32_le(patch, footer + 8).ok_or(LoadError::BrokenPatch)?;
+  if checksum::crc32(&patch[..footer + 8]) != patch_crc {
+    return Err(LoadError::BrokenPatch);
+  }
+  let rom_crc = checksum::crc32(rom);
+  if rom_crc != source_crc {
+    return Err(LoadError::PatchForOtherRom(source_crc, rom_crc));
+  }
+
+  let mut reader = BpsReader { patch, offset: 4, end: footer };
+  let source_size = reader.number().ok_or(LoadError::BrokenPatch)?;
+  let target_size = reader.number().ok_or(LoadError::BrokenPatch)?;
+  let metadata_size = reader.number().ok_or(LoadError::BrokenPatch)?;
+  if source_size != rom.len() || target_size > MAX_SIZE {
+    return Err(LoadError::BrokenPatch);
+  }
+  reader.offset = reader.offset.checked_add(metadata_size).filter(|&offset| offset <= reader.end).ok_or(LoadError::BrokenPatch)?;
+
+  let image = (|| {
+    let mut image = Vec::with_capacity(target_size);
+    let mut source_offset: usize = 0;
+    let mut target_offset: usize = 0;
+    while reader.offset < reader.end {
+      let command = reader.number()?;
+      let length = (command >> 2) + 1;
+      //Checked before anything is written, since a target copy can repeat a byte as often as it says.
+      image.len().checked_add(length).filter(|&end| end <= target_size)?;
+      match command & 0b11 {
+        0 => {
+          //Source read: the same bytes as the original ROM, at the same place.
+          let start = image.len();
+          image.extend_from_slice(rom.get(start..start + length)?);
+        },
+        1 => {
+          //Target read: bytes straight out of the patch.
+          let data = patch.get(reader.offset..reader.offset + length).filter(|_| reader.offset + length <= reader.end)?;
+          image.extend_from_slice(data);
+          reader.offset += length;
+        },
+        2 => {
+          //Source copy: bytes from anywhere in the original ROM.
+          source_offset = reader.relative(source_offset)?;
+          image.extend_from_slice(rom.get(source_offset..source_offset.checked_add(length)?)?);
+          source_offset += length;
+        },
+        _ => {
+          //Target copy: bytes already written. They can overlap with what is being written, so go one byte at a time.
+          target_offset = reader.relative(target_offset)?;
+          for _ in 0..length {
+            let byte = *image.get(target_offset)?;
+            image.push(byte);
+            target_offset += 1;
+          }
+        },
+      }
+    }
+    Some(image)
+  })().ok_or(LoadError::BrokenPatch)?;
+
+  if image.len() != target_size || checksum::crc32(&image) != target_crc {
+    return Err(LoadError::BrokenPatch);
+  }
+  Ok(image)
+}
+
+struct BpsReader<'a> {
+  patch: &'a [u8],
+  offset: usize,
+  end: usize, //Where the checksums start.
+}
+
+impl BpsReader<'_> {
+  /// Numbers are 7 bits per byte, lowest first. The last byte has its top bit set.
+  /// Each byte after the first also adds one step, so no number has two encodings.
+  fn number(&mut self) -> Option<usize> {
+    let mut number: usize = 0;
+    let mut shift: usize = 1;
+    loop {
+      if self.offset >= self.end {
+        return None;
+      }
+      let byte = self.patch[self.offset];
+      self.offset += 1;
+      number = number.checked_add((byte as usize & 0x7F).checked_mul(shift)?)?;
+      if byte & 0x80 != 0 {
+        return Some(number);
+      }
+      shift = shift.checked_mul(0x80)?;
+      number = number.checked_add(shift)?;
+    }
+  }
+
+  /// A move forward or backward from the offset. The lowest bit is the sign.
+  fn relative(&mut self, offset: usize) -> Option<usize> {
+    let number = self.number()?;
+    if number & 1 == 0 {
+      offset.checked_add(number >> 1)
+    } else {
+      offset.checked_sub(number >> 1)
+    }
+  }
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> Option<u16> {
+  Some(u16::from_be_bytes(bytes.get(offset..offset + 2)?.try_into().ok()?))
+}
+
+fn read_u24(bytes: &[u8], offset: usize) -> Option<usize> {
+  let bytes = bytes.get(offset..offset + 3)?;
+  Some(((bytes[0] as usize) << 16) | ((bytes[1] as usize) << 8) | bytes[2] as usize)
+}
+
+fn read_u32_le(bytes: &[u8], offset: usize) -> Option<u32> {
+  Some(u32::from_le_bytes(bytes.get(offset..offset + 4)?.try_into().ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn ips(records: &[(usize, &[u8])]) -> Vec<u8> {
+    let mut patch = b"PATCH".to_vec();
+    for (address, data) in records {
+      patch.extend_from_slice(&(*address as u32).to_be_bytes()[1..]);
+      patch.extend_from_slice(&(data.len() as u16).to_be_bytes());
+      patch.extend_from_slice(data);
+    }
+    patch.extend_from_slice(b"EOF");
+    patch
+  }
+
+  fn bps_number(mut number: usize, patch: &mut Vec<u8>) {
+    loop {
+      let byte = (number & 0x7F) as u8;
+      number >>= 7;
+      if number == 0 {
+        patch.push(byte | 0x80);
+        return;
+      }
+      patch.push(byte);
+      number -= 1;
+    }
+  }
+
+  /// A BPS patch which keeps the first half of the source and writes the rest from the patch.
+  fn bps(source: &[u8], target: &[u8], metadata_size: usize) -> Vec<u8> {
+    let mut patch = b"BPS1".to_vec();
+    bps_number(source.len(), &mut patch);
+    bps_number(target.len(), &mut patch);
+    bps_number(metadata_size, &mut patch);
+    let kept = source.len() / 2;
+    bps_number((kept - 1) << 2, &mut patch);
+    bps_number(((target.len() - kept - 1) << 2) | 1, &mut patch);
+    patch.extend_from_slice(&target[kept..]);
+    bps_footer(patch, source, target)
+  }
+
+  fn bps_footer(mut patch: Vec<u8>, source: &[u8], target: &[u8]) -> Vec<u8> {
+    patch.extend_from_slice(&checksum::crc32(source).to_le_bytes());
+    patch.extend_from_slice(&checksum::crc32(target).to_le_bytes());
+    let patch_crc = checksum::crc32(&patch);
+    patch.extend_from_slice(&patch_crc.to_le_bytes());
+    patch
+  }
+
+  #[test]
+  fn ips_records_and_growth() {
+    let rom = [0x55, 0x2B, 0, 0];
+    assert_eq!(apply(&rom, &ips(&[(2, &[7, 8]), (5, &[9])])).ok(), Some(vec![0x55, 0x2B, 7, 8, 0, 9]));
+  }
+
+  #[test]
+  fn ips_run_length_and_truncation() {
+    let mut patch = b"PATCH".to_vec();
+    patch.extend_from_slice(&[0, 0, 1, 0, 0, 0, 3, 0xEE]);
+    patch.extend_from_slice(b"EOF");
+    patch.extend_from_slice(&[0, 0, 3]);
+    assert_eq!(apply(&[1, 2, 3, 4, 5], &patch).ok(), Some(vec![1, 0xEE, 0xEE]));
+  }
+
+  #[test]
+  fn ips_cut_short_is_broken() {
+    let mut patch = ips(&[(0, &[1, 2, 3])]);
+    patch.truncate(patch.len() - 5);
+    assert!(matches!(apply(&[0; 4], &patch), Err(LoadError::BrokenPatch)));
+  }
+
+  #[test]
+  fn bps_applies_and_is_checked() {
+    let source = [0x55, 0x2B, 1, 2, 3, 4];
+    let target = [0x55, 0x2B, 1, 9, 9, 9, 9];
+    let patch = bps(&source, &target, 0);
+    assert_eq!(source_crc(&patch), Some(checksum::crc32(&source)));
+    assert_eq!(apply(&source, &patch).ok(), Some(target.to_vec()));
+    assert!(matches!(apply(&target, &patch), Err(LoadError::PatchForOtherRom(_, _))));
+
+    let mut damaged = patch.clone();
+    damaged[6] ^= 1;
+    assert!(matches!(apply(&source, &damaged), Err(LoadError::BrokenPatch)));
+  }
+
+  #[test]
+  fn bps_metadata_past_the_end_is_broken() {
+    let source = [0x55, 0x2B, 1, 2];
+    let patch = bps(&source, &[0x55, 0x2B, 3, 4], usize::MAX >> 8);
+    assert!(matches!(apply(&source, &patch), Err(LoadError::BrokenPatch)));
+  }
+
+  #[test]
+  fn bps_target_copy_repeats_bytes() {
+    let source = [0x55, 0x2B];
+    let target = [0x55, 0x2B, 0x2B, 0x2B, 0x2B];
+    let mut patch = b"BPS1".to_vec();
+    for number in [2, 5, 0, 1 << 2] {
+      bps_number(number, &mut patch); //Sizes, no metadata, then a source read of 2.
+    }
+    bps_number((2 << 2) | 3, &mut patch); //Target copy of 3, starting back at the byte just written.
+    bps_number(1 << 1, &mut patch); //Forward one from the start.
+    let patch = bps_footer(patch, &source, &target);
+    assert_eq!(apply(&source, &patch).ok(), Some(target.to_vec()));
+  }
+
+  #[test]
+  fn bps_endless_target_copy_is_broken() {
+    let source = [0x55, 0x2B];
+    let mut patch = b"BPS1".to_vec();
+    for number in [2, 4, 0, 1 << 2] {
+      bps_number(number, &mut patch);
+    }
+    //A target copy of a trillion bytes, repeating the last one, is refused before anything is copied.
+    bps_number(((1usize << 40) << 2) | 3, &mut patch);
+    bps_number(1 << 1, &mut patch); //Forward one from the start.
+    let patch = bps_footer(patch, &source, &[0x55, 0x2B, 0x2B, 0x2B]);
+    assert!(matches!(apply(&source, &patch), Err(LoadError::BrokenPatch)));
+  }
+
+  #[test]
+  fn not_a_patch() {
+    assert!(!is_patch(&[0x55]));
+    assert_eq!(source_crc(b"PATCHEOF"), None);
+    assert!(matches!(apply(&[0x55], b"nothing"), Err(LoadError::NotAPatch)));
+  }
+}
@@ -0,0 +1,158 @@
+use chips::fairchild_f8;
+use std::collections::VecDeque;
+use super::watchpoints;
+
+// Debug view of every IN, OUT, INS and OUTS, with what the value means to the console.
+// Like watchpoints, we decode each instruction to see which port it uses. The value is in A afterwards either way.
+//
+// What the console ports mean, as the program writes them. Most are inverted, the same as the hardware sees them:
+//   Port 0  0x20 is the video write strobe, the sixth bit. Its rising edge writes the pixel.
+//           0x40 clear lets the controllers be read.
+//           Reading gives the console buttons in the low 4 bits, low when pressed.
+//   Port 1  Bits 6 - 7 are the pixel color, inverted. Reading gives the right controller, low when pressed.
+//   Port 4  Bits 0 - 6 are the pixel column, inverted. Reading gives the left controller.
+//   Port 5  Bits 0 - 5 are the pixel row, inverted. Bits 6 - 7 are the tone.
+// Any other port belongs to the cartridge, like the 2102 RAM.
+
+const LOG_LENGTH: usize = 200;
+const STROBE: u8 = 0x20;
+const CONTROLLERS_OFF: u8 = 0x40;
+const CONSOLE_BUTTONS: [&str; 4] = ["Time", "Mode", "Hold", "Start"];
+const CONTROLLER_BUTTONS: [&str; 8] = ["Right", "Left", "Back", "Forward", "Counterclockwise", "Clockwise", "Pull", "Push"];
+
+#[derive(Clone, Copy)]
+struct PortEvent {
+  frame: u64,
+  tick: u64,
+  port: u8,
+  write: bool,
+  value: u8,
+  video: Option<(u8, u8, u8)>,  //The pixel written by the strobe: column, row, color.
+}
+
+pub(super) struct PortMonitor {
+  enabled: bool,
+  accesses: Vec<(watchpoints::Space, u16, watchpoints::Access)>, //What the instruction now running reads and writes.
+  latches: [u8; 6],  //The last values written to the console ports.
+  frame: u64,
+  tick: u64,
+  log: VecDeque<PortEvent>,
+  log_changed: bool,
+  port_log: Option<web_sys::Element>,
+}
+
+impl PortMonitor {
+  pub fn new() -> Self {
+    let document = web_sys::window().unwrap().document().unwrap();
+    Self {
+      enabled: false,
+      accesses: vec![],
+      latches: [0; 6],
+      frame: 0,
+      tick: 0,
+      log: VecDeque::with_capacity(LOG_LENGTH + 1),
+      log_changed: true,
+      port_log: document.get_element_by_id("port_log"),
+    }
+  }
+
+  /// Decoding every instruction costs a bit, so it is off until asked for.
+  pub fn set_enabled(&mut self, enabled: bool) {
+    self.enabled = enabled;
+    self.accesses.clear();
+  }
+
+  pub fn clear(&mut self) {
+    self.log.clear();
+    self.log_changed = true;
+  }
+
+  /// Called after every instruction.
+  pub fn run_cycle(&mut self, board: &fairchild_f8::Board, clock_ticks: usize) {
+    self.tick += clock_ticks as u64;
+    if !self.enabled {
+      return;
+    }
+    let accesses = std::mem::replace(&mut self.accesses, watchpoints::decode_accesses(board));
+    for (space, port, access) in accesses {
+      if space != watchpoints::Space::Port {
+        continue;
+      }
+      let port = port as u8;
+      let write = access == watchpoints::Access::Write;
+      let value = board.cpu.a;
+      let mut video = None;
+      if write && (port as usize) < self.latches.len() {
+        if port == 0 && value & STROBE != 0 && self.latches[0] & STROBE == 0 {
+          video = Some(((self.latches[4] | 0x80) ^ 0xFF, (self.latches[5] | 0xC0) ^ 0xFF, ((self.latches[1] ^ 0xFF) >> 6) & 0x03));
+        }
+        self.latches[port as usize] = value;
+      }
+      let event = PortEvent { frame: self.frame, tick: self.tick, port, write, value, video };
+      log::debug!(target: "ports", "Frame {} tick {}: {}", event.frame, event.tick, describe(&event));
+      self.log.push_back(event);
+      if self.log.len() > LOG_LENGTH {
+        self.log.pop_front();
+      }
+      self.log_changed = true;
+    }
+  }
+
+  pub fn run_refresh_cycle(&mut self) {
+    self.frame += 1;
+  }
+
+  pub fn print(&mut self) {
+    if !self.log_changed {
+      return;
+    }
+    self.log_changed = false;
+    let tbody = match &self.port_log {
+      Some(tbody) => tbody,
+      None => return,
+    };
+    let mut html = String::new();
+    for event in self.log.iter().rev() {
+      html.push_str(&format!(
+        "<tr><td>{}</td><td>{}</td><td>{:02X}</td><td>{}</td><td>{:02X} {:08b}</td><td>{}</td></tr>",
+        event.frame, event.tick, event.port, if event.write { "Out" } else { "In" }, event.value, event.value, meaning(event),
+      ));
+    }
+    tbody.set_inner_html(&html);
+  }
+}
+
+fn describe(event: &PortEvent) -> String {
+  format!("{} port {:02X} = {:02X} {}", if event.write { "Out" } else { "In" }, event.port, event.value, meaning(event))
+}
+
+/// What the value does, for the console ports.
+fn meaning(event: &PortEvent) -> String {
+  match (event.port, event.write) {
+    (0, true) => {
+      let mut meaning = match event.video {
+        Some((column, row, color)) => format!("Write strobe: pixel {},{} color {}", column, row, color),
+        None if event.value & STROBE != 0 => "Strobe high".to_string(),
+        None => "Strobe low".to_string(),
+      };
+      if event.value & CONTROLLERS_OFF == 0 {
+        meaning.push_str(", controllers on");
+      }
+      meaning
+    },
+    (1, true) => format!("Color {}", ((event.value ^ 0xFF) >> 6) & 0x03),
+    (4, true) => format!("Column {}", (event.value | 0x80) ^ 0xFF),
+    (5, true) => format!("Row {}, tone {}", (event.value | 0xC0) ^ 0xFF, event.value >> 6),
+    (0, false) => pressed(event.value, &CONSOLE_BUTTONS),
+    (1, false) => format!("Right controller: {}", pressed(event.value, &CONTROLLER_BUTTONS)),
+    (4, false) => format!("Left controller: {}", pressed(event.value, &CONTROLLER_BUTTONS)),
+    (2 | 3 | 5, _) => String::new(),
+    _ => "Cartridge".to_string(),
+  }
+}
+
+/// The buttons whose bits are low.
+fn pressed(value: u8, names: &[&str]) -> String {
+  let names: Vec<&str> = names.iter().enumerate().filter(|(bit, _)| value & (1 << bit) == 0).map(|(_, name)| *name).collect();
+  if names.is_empty() { "Nothing pressed".to_string() } else { names.join(", ") }
+}
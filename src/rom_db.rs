@@ -0,0 +1,281 @@
+use super::checksum;
+use super::free_bios;
+use super::loader::{RomKind, Roms};
+
+// Every ROM we know about, identified by its CRC-32 and/or SHA-1.
+// The BIOS is made of two 1K chips, so each chip is its own entry. A 2K BIOS file gets split in half before looking it up.
+//
+// Cheats are (name, code), in the format of the cheats module. Like hashes, only codes which were tried belong here,
+// and none have been yet. The cheats module lists and searches them.
+//
+// Only hashes of verified dumps belong here. The Videocarts don't have theirs yet, so until they are filled in,
+// a cartridge is also recognized by the Videocart number in its .chf title or file name, like "Videocart 10 - Maze.bin".
+// The page says when that happened, with the hashes, so they can be checked and added. ROMs we don't recognize run just the same.
+
+#[derive(Clone, Copy, PartialEq)]
+pub(super) enum CartridgeType {
+  Rom,  //Nothing but ROM
+  Sram2102, //A 2102 1K x 1 bit static RAM, on I/O ports.
+  Smi3853,  //A 3853 Static Memory Interface, for its timer and interrupts.
+  Schach, //The chess cartridge, with 2K of RAM.
+  Multicart,  //Flash with switchable banks.
+}
+
+impl CartridgeType {
+  pub fn from_name(name: &str) -> Option<Self> {
+    match name {
+      "rom" => Some(CartridgeType::Rom),
+      "2102" => Some(CartridgeType::Sram2102),
+      "3853" => Some(CartridgeType::Smi3853),
+      "schach" => Some(CartridgeType::Schach),
+      "multicart" => Some(CartridgeType::Multicart),
+      _ => None,
+    }
+  }
+
+  pub fn name(&self) -> &'static str {
+    match self {
+      CartridgeType::Rom => "ROM",
+      CartridgeType::Sram2102 => "ROM + 2102 SRAM",
+      CartridgeType::Smi3853 => "ROM + 3853 SMI",
+      CartridgeType::Schach => "Schach (ROM, its RAM isn't emulated)",
+      CartridgeType::Multicart => "Multicart",
+    }
+  }
+}
+
+pub(super) struct RomInfo {
+  pub title: &'static str,
+  pub kind: RomKind,
+  pub crc32: Option<u32>,
+  pub sha1: Option<&'static str>,
+  pub status: &'static str, //How well it runs, as shown in the README.
+  pub cartridge_type: CartridgeType,
+  pub labels: Option<[&'static str; 4]>,  //What the 4 console buttons do in this game.
+  pub quirks: &'static str,
+  pub cheats: &'static [(&'static str, &'static str)],
+}
+
+const fn bios(title: &'static str, crc32: u32) -> RomInfo {
+  RomInfo { title, kind: RomKind::Bios, crc32: Some(crc32), sha1: None, status: "Works", cartridge_type: CartridgeType::Rom, labels: None, quirks: "", cheats: &[] }
+}
+
+const fn cart(title: &'static str, status: &'static str, cartridge_type: CartridgeType, quirks: &'static str) -> RomInfo {
+  RomInfo { title, kind: RomKind::Cartridge, crc32: None, sha1: None, status, cartridge_type, labels: None, quirks, cheats: &[] }
+}
+
+pub(super) const ROMS: &[RomInfo] = &[
+  bios("SL31253 BIOS (Channel F)", 0x04694ED9),
+  bios("SL31254 BIOS (Channel F)", 0x9C047BA3),
+  bios("SL90025 BIOS (Luxor Video Entertainment System)", 0x015C1E38),
+  cart("Videocart 1 - Tic-Tac-Toe, etc", "Works", CartridgeType::Rom, ""),
+  cart("Videocart 2 - Desert Fox, etc", "Works", CartridgeType::Rom, ""),
+  cart("Videocart 3 - Video Blackjack", "Works (Hit Mode then 3 to switch to the right player)", CartridgeType::Rom, "Hit Mode then 3 to switch to the right player."),
+  cart("Videocart 4 - Spitfire", "Works", CartridgeType::Rom, ""),
+  cart("Videocart 5 - Space War", "Works", CartridgeType::Rom, ""),
+  cart("Videocart 6 - Math Quiz", "Works", CartridgeType::Rom, ""),
+  cart("Videocart 7 - Math Quiz 2", "Works-Buggy", CartridgeType::Rom, ""),
+  cart("Videocart 8 - Magic Numbers, etc", "Works", CartridgeType::Rom, ""),
+  cart("Videocart 9 - Drag Race", "Works?", CartridgeType::Rom, ""),
+  cart("Videocart 10 - Maze", "Works", CartridgeType::Sram2102, ""),
+  cart("Videocart 11 - Backgammon, etc", "Works?", CartridgeType::Rom, ""),
+  cart("Videocart 12 - Baseball", "Works?", CartridgeType::Rom, ""),
+  cart("Videocart 13 - Robot War, etc", "Works", CartridgeType::Rom, ""),
+  cart("Videocart 14 - Sonar Search", "Works", CartridgeType::Rom, ""),
+  cart("Videocart 15 - Memory Match", "Works", CartridgeType::Rom, ""),
+  cart("Videocart 16 - Dodge' It", "Works", CartridgeType::Rom, ""),
+  cart("Videocart 17 - Pinball Challenge", "Works", CartridgeType::Rom, ""),
+  cart("Videocart 18 - Hangman", "Works", CartridgeType::Sram2102, ""),
+  cart("Videocart 19 - Checkers", "Works but sound is slightly off", CartridgeType::Rom, "Sound is slightly off."),
+  cart("Videocart 20 - Video Whizball", "Works", CartridgeType::Rom, ""),
+  cart("Videocart 21 - Bowling", "Works", CartridgeType::Rom, ""),
+  cart("Videocart 22 - Slot Machine", "Works-Buggy", CartridgeType::Rom, ""),
+  cart("Videocart 23 - Galactic Space Wars", "Works", CartridgeType::Rom, ""),
+  cart("Videocart 24 - Pro Football", "Works?", CartridgeType::Rom, ""),
+  cart("Videocart 25 - Casino Poker", "Works?", CartridgeType::Rom, ""),
+  cart("Videocart 26 - Alien Invasion", "Unclear", CartridgeType::Rom, ""),
+];
+
+pub(super) struct Identified {
+  pub crc32: u32,
+  pub sha1: String,
+  pub info: Option<&'static RomInfo>,
+}
+
+pub(super) fn identify(bytes: &[u8]) -> Identified {
+  let crc32 = checksum::crc32(bytes);
+  let sha1 = checksum::to_hex(&checksum::sha1(bytes));
+  let info = ROMS.iter().find(|info| {
+    let crc32_matches = info.crc32.map(|info_crc32| info_crc32 == crc32);
+    let sha1_matches = info.sha1.map(|info_sha1| info_sha1.eq_ignore_ascii_case(&sha1));
+    match (crc32_matches, sha1_matches) {
+      (None, None) => false,
+      (crc32_matches, sha1_matches) => crc32_matches.unwrap_or(true) && sha1_matches.unwrap_or(true),
+    }
+  });
+  Identified { crc32, sha1, info }
+}
+
+/// Identifies each 1K chip of the BIOS.
+pub(super) fn identify_bios(bytes: &[u8]) -> Vec<Identified> {
+  if bytes.len() == 0x800 {
+    bytes.chunks(0x400).map(identify).collect()
+  } else {
+    vec![identify(bytes)]
+  }
+}
+
+/// The cartridge database entry, if we recognize the cartridge. By hash, then by name.
+pub(super) fn cartridge_info(roms: &Roms) -> Option<&'static RomInfo> {
+  let bytes = roms.cartridge.as_deref()?;
+  identify(bytes).info.filter(|info| info.kind == RomKind::Cartridge).or_else(|| identify_by_name(roms))
+}
+
+/// The Videocart with the number in the .chf title or the file name.
+fn identify_by_name(roms: &Roms) -> Option<&'static RomInfo> {
+  [&roms.cartridge_title, &roms.cartridge_name].into_iter().flatten().find_map(|name| {
+    let number = videocart_number(name)?;
+    ROMS.iter().find(|info| info.kind == RomKind::Cartridge && info.title.starts_with(&format!("Videocart {} ", number)))
+  })
+}
+
+/// "Videocart 10", "videocart-10" and "Videocart_10" all give 10.
+fn videocart_number(name: &str) -> Option<u32> {
+  let name = name.to_lowercase();
+  let start = name.rfind("videocart")? + "videocart".len();
+  let number: String = name[start..].trim_start_matches([' ', '-', '_', '#']).chars().take_while(|c| c.is_ascii_digit()).collect();
+  number.parse().ok()
+}
+
+/// Shows what was loaded in the page, and the game's labels on the console buttons.
+pub(super) fn print_rom_info(roms: &Roms) {
+  let bios = roms.bios.as_deref();
+  let cartridge = roms.cartridge.as_deref();
+  let document = web_sys::window().unwrap().document().unwrap();
+  if let Some(element) = document.get_element_by_id("rom_info") {
+    let mut lines = vec![];
+    match bios {
+      Some(bytes) => {
+        let titles: Vec<String> = identify_bios(bytes).iter().map(describe).collect();
+        lines.push(format!("BIOS: {}", titles.join(" + ")));
+      },
+      None => lines.push(format!("BIOS: {}", free_bios::TITLE)),
+    }
+    if let Some(bytes) = cartridge {
+      let identified = identify(bytes);
+      match (&roms.cartridge_title, identified.info, cartridge_info(roms)) {
+        (_, None, Some(info)) => lines.push(format!(
+          "Cartridge: {} (recognized by name, CRC32 {:08X}, SHA-1 {})", info.title, identified.crc32, identified.sha1,
+        )),
+        (Some(title), None, None) => lines.push(format!("Cartridge: {} ({})", title, describe(&identified))),
+        _ => lines.push(format!("Cartridge: {}", describe(&identified))),
+      }
+      lines.push(format!("Hardware: {}", roms.resolved_cartridge_type().name()));
+      if let Some(info) = cartridge_info(roms) {
+        if !info.quirks.is_empty() {
+          lines.push(format!("Note: {}", info.quirks));
+        }
+      }
+    }
+    element.set_text_content(Some(&lines.join("\n")));
+  }
+
+  let labels = cartridge_info(roms).and_then(|info| info.labels);
+  if let Some(console) = document.get_element_by_id("console") {
+    for code in 1..=4 {
+      if let Ok(Some(button)) = console.query_selector(&format!("[code=\"{}\"]", code)) {
+        match labels {
+          Some(labels) => { let _ = button.set_attribute("title", labels[code - 1]); },
+          None => { let _ = button.remove_attribute("title"); },
+        }
+      }
+    }
+  }
+}
+
+fn describe(identified: &Identified) -> String {
+  match identified.info {
+    Some(info) => info.title.to_string(),
+    None => format!("Unknown (CRC32 {:08X}, SHA-1 {})", identified.crc32, identified.sha1),
+  }
+}
+
+/// The compatibility table of the README, in Markdown.
+pub(super) fn compatibility_table() -> String {
+  let mut table = String::from("ROM | Status\n----|-------\nBIOS alone | Works\n");
+  for info in ROMS.iter().filter(|info| info.kind == RomKind::Cartridge) {
+    table.push_str(&format!("{} | {}\n", info.title, info.status));
+  }
+  table
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// Bytes with the given CRC-32: 4 bytes are added which take the CRC from that of `bytes` to `crc32`.
+  fn with_crc32(mut bytes: Vec<u8>, crc32: u32) -> Vec<u8> {
+    //The CRC steps are linear, so run them backwards from the wanted register to see what the 4 bytes have to be.
+    let mut register = !crc32;
+    for _ in 0..32 {
+      register = if register & 0x80000000 != 0 { ((register ^ 0xEDB88320) << 1) | 1 } else { register << 1 };
+    }
+    let patch = register ^ !checksum::crc32(&bytes);
+    bytes.extend_from_slice(&patch.to_le_bytes());
+    bytes
+  }
+
+  #[test]
+  fn bios_is_matched_by_crc32() {
+    let chip = with_crc32(vec![0x55; 0x3FC], 0x04694ED9);
+    assert_eq!(checksum::crc32(&chip), 0x04694ED9);
+    let identified = identify(&chip);
+    assert_eq!(identified.crc32, 0x04694ED9);
+    assert_eq!(identified.info.map(|info| info.title), Some("SL31253 BIOS (Channel F)"));
+  }
+
+  #[test]
+  fn bios_chips_are_matched_in_one_file() {
+    let mut bios = with_crc32(vec![0; 0x3FC], 0x04694ED9);
+    bios.extend(with_crc32(vec![0; 0x3FC], 0x9C047BA3));
+    let titles: Vec<_> = identify_bios(&bios).iter().map(|identified| identified.info.map(|info| info.title)).collect();
+    assert_eq!(titles, [Some("SL31253 BIOS (Channel F)"), Some("SL31254 BIOS (Channel F)")]);
+  }
+
+  #[test]
+  fn unknown_rom() {
+    let identified = identify(b"Not a ROM we know");
+    assert!(identified.info.is_none());
+    assert_eq!(identified.sha1.len(), 40);
+    assert_eq!(describe(&identified), format!("Unknown (CRC32 {:08X}, SHA-1 {})", identified.crc32, identified.sha1));
+  }
+
+  #[test]
+  fn cartridge_is_matched_by_name() {
+    let mut roms = Roms::default();
+    roms.cartridge = Some(vec![0x55, 0x2B, 0, 0]);
+    roms.cartridge_name = Some("Videocart-10 (USA).bin".to_string());
+    assert_eq!(cartridge_info(&roms).map(|info| info.title), Some("Videocart 10 - Maze"));
+    //The .chf title is tried first.
+    roms.cartridge_title = Some("Videocart 1 - Tic-Tac-Toe".to_string());
+    assert_eq!(cartridge_info(&roms).map(|info| info.title), Some("Videocart 1 - Tic-Tac-Toe, etc"));
+    roms.cartridge_title = None;
+    roms.cartridge_name = Some("Pac-Man.bin".to_string());
+    assert!(cartridge_info(&roms).is_none());
+    roms.cartridge = None;
+    roms.cartridge_name = Some("Videocart 10.bin".to_string());
+    assert!(cartridge_info(&roms).is_none());
+  }
+
+  #[test]
+  fn videocart_numbers() {
+    assert_eq!(videocart_number("Videocart 10 - Maze.bin"), Some(10));
+    assert_eq!(videocart_number("videocart-10"), Some(10));
+    assert_eq!(videocart_number("Videocart_10"), Some(10));
+    assert_eq!(videocart_number("VIDEOCART #3"), Some(3));
+    assert_eq!(videocart_number("Channel F Videocart 1 (Videocart 26).chf"), Some(26));
+    assert_eq!(videocart_number("Videocart"), None);
+    assert_eq!(videocart_number("Videocart - Maze"), None);
+    assert_eq!(videocart_number("Maze.bin"), None);
+  }
+}
@@ -0,0 +1,140 @@
+use chips::fairchild_f8;
+use std::cell::RefCell;
+use super::cartridge;
+use super::checksum;
+use super::download;
+use super::loader;
+use super::scheduler;
+
+// Cartridges with RAM lose it when the console turns off, but homebrew authors want high scores kept.
+// So we act like there is a battery: the RAM is kept in the browser's localStorage, under the SHA-1 of the cartridge ROM.
+//
+// The RAM is written out every few seconds if it changed, and when the page is closed.
+// The page can't wait for our loop when it closes, so every frame the latest RAM is left where flush() can find it.
+// Scripts get the RAM as a file the same way, through file().
+
+const SAVE_INTERVAL_SECONDS: u64 = 5;
+
+thread_local! {
+  static UNSAVED: RefCell<Option<(String, Vec<u8>)>> = const { RefCell::new(None) };
+  static CURRENT: RefCell<Option<Vec<u8>>> = const { RefCell::new(None) }; //The RAM as of the last frame.
+}
+
+pub(super) struct SaveRam {
+  key: Option<String>,  //None if the cartridge has no RAM.
+  saved: Vec<u8>,
+  frame_count: u64,
+}
+
+impl SaveRam {
+  /// Restores what was saved for this cartridge, if anything.
+  pub fn new(roms: &loader::Roms, cartridge: &mut cartridge::Cartridge, board: &mut fairchild_f8::Board) -> Self {
+    let key = match (&roms.cartridge, cartridge.save_ram(board)) {
+      (Some(image), Some(_)) => Some(format!("fairchild_ves.ram.{}", checksum::to_hex(&checksum::sha1(image)))),
+      _ => None,
+    };
+    let mut save_ram = Self {
+      key,
+      saved: vec![],
+      frame_count: 0,
+    };
+    if let Some(bytes) = save_ram.key.as_deref().and_then(read_storage) {
+      cartridge.load_ram(board, &bytes);
+      save_ram.saved = bytes;
+    }
+    let current = save_ram.key.as_ref().and_then(|_| cartridge.save_ram(board));
+    CURRENT.with(|current_ram| *current_ram.borrow_mut() = current);
+    save_ram
+  }
+
+  /// Called once per frame. The timing says how many frames make up the save interval.
+  pub fn run_refresh_cycle(&mut self, cartridge: &cartridge::Cartridge, board: &fairchild_f8::Board, timing: scheduler::Timing) {
+    let key = match &self.key {
+      Some(key) => key,
+      None => return,
+    };
+    self.frame_count += 1;
+    if let Some(ram) = cartridge.save_ram(board) {
+      CURRENT.with(|current| {
+        let mut current = current.borrow_mut();
+        if current.as_ref() != Some(&ram) {
+          *current = Some(ram.clone());
+        }
+      });
+      if ram != self.saved {
+        if self.frame_count >= SAVE_INTERVAL_SECONDS * timing.frames_per_second {
+          write_storage(key, &ram);
+          self.saved = ram;
+          self.frame_count = 0;
+          UNSAVED.with(|unsaved| *unsaved.borrow_mut() = None);
+        } else {
+          UNSAVED.with(|unsaved| *unsaved.borrow_mut() = Some((key.clone(), ram)));
+        }
+      }
+    }
+  }
+
+  /// Saves right away. Used before the cartridge is swapped out.
+  pub fn save(&mut self, cartridge: &cartridge::Cartridge, board: &fairchild_f8::Board) {
+    if let (Some(key), Some(ram)) = (&self.key, cartridge.save_ram(board)) {
+      write_storage(key, &ram);
+      self.saved = ram;
+    }
+    UNSAVED.with(|unsaved| *unsaved.borrow_mut() = None);
+  }
+
+  pub fn export(&self, cartridge: &cartridge::Cartridge, board: &fairchild_f8::Board) {
+    if let Some(ram) = cartridge.save_ram(board) {
+      download::download("cartridge.sav", &ram);
+    }
+  }
+
+  pub fn import(&mut self, cartridge: &mut cartridge::Cartridge, board: &mut fairchild_f8::Board, bytes: &[u8]) {
+    cartridge.load_ram(board, bytes);
+    self.save(cartridge, board);
+  }
+
+  /// Forgets the saved RAM, and empties the RAM of the running cartridge, as if the battery had run out.
+  pub fn clear(&mut self, cartridge: &mut cartridge::Cartridge, board: &mut fairchild_f8::Board) {
+    if let Some(key) = &self.key {
+      if let Some(storage) = local_storage() {
+        let _ = storage.remove_item(key);
+      }
+    }
+    if let Some(ram) = cartridge.save_ram(board) {
+      cartridge.load_ram(board, &vec![0; ram.len()]);
+    }
+    //Only what the game writes from now on gets saved again.
+    self.saved = cartridge.save_ram(board).unwrap_or_default();
+    self.frame_count = 0;
+    UNSAVED.with(|unsaved| *unsaved.borrow_mut() = None);
+  }
+}
+
+/// Writes out RAM which changed since the last save. The page calls this when it closes.
+pub(super) fn flush() {
+  if let Some((key, ram)) = UNSAVED.with(|unsaved| unsaved.borrow_mut().take()) {
+    write_storage(&key, &ram);
+  }
+}
+
+/// The cartridge RAM as of the last frame, the same as the export. Empty if the cartridge has no RAM.
+pub(super) fn file() -> Vec<u8> {
+  CURRENT.with(|current| current.borrow().clone().unwrap_or_default())
+}
+
+pub(super) fn local_storage() -> Option<web_sys::Storage> {
+  web_sys::window()?.local_storage().ok()?
+}
+
+/// localStorage only holds strings, so the bytes are stored as hex.
+fn read_storage(key: &str) -> Option<Vec<u8>> {
+  let text = local_storage()?.get_item(key).ok()??;
+  (0..text.len() / 2).map(|index| u8::from_str_radix(text.get(index * 2..index * 2 + 2)?, 16).ok()).collect()
+}
+
+fn write_storage(key: &str, bytes: &[u8]) {
+  if let Some(storage) = local_storage() {
+    let _ = storage.set_item(key, &checksum::to_hex(bytes));
+  }
+}
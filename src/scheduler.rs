@@ -0,0 +1,145 @@
+use wasm_bindgen::prelude::*;
+
+/// How fast the CPU runs and how many frames the TV shows per second. These differ between regions.
+#[derive(Clone, Copy, PartialEq)]
+pub(super) struct Timing {
+  pub ticks_per_second: u64,
+  pub frames_per_second: u64,
+}
+
+impl Timing {
+  pub const PAL: Timing = Timing { ticks_per_second: 2_000_000, frames_per_second: 50 };  // Each tick takes 500 ns. 1 / 0.0000005 = 2_000_000
+  pub const NTSC: Timing = Timing { ticks_per_second: 1_789_773, frames_per_second: 60 }; // Half of the 3.579545 MHz color burst crystal.
+
+  pub fn from_param(param: Option<&String>) -> Self {
+    match param.map(|p| p.as_str()) {
+      Some("ntsc") => Timing::NTSC,
+      _ => Timing::PAL,
+    }
+  }
+
+  pub fn ticks_per_frame(&self) -> u64 {
+    self.ticks_per_second / self.frames_per_second
+  }
+}
+
+//If the browser falls far behind (tab in background, debugger, etc), don't try to catch up all at once.
+const MAX_FRAMES_PER_WAIT: u32 = 4;
+
+#[derive(Clone, Copy, PartialEq)]
+pub(super) enum Pacing {
+  AnimationFrame, //Follow the display with requestAnimationFrame. Sound is stretched slightly to keep up.
+  Audio,  //Follow the audio clock. Only run a frame when the queued sound drops below the target latency.
+}
+
+impl Pacing {
+  pub fn from_param(param: Option<&String>) -> Self {
+    match param.map(|p| p.as_str()) {
+      Some("video") | Some("raf") => Pacing::AnimationFrame,
+      _ => Pacing::Audio,
+    }
+  }
+}
+
+/// Decides how many frames to emulate, and how many ticks make up a frame.
+/// Instructions don't end exactly at a frame boundary, so the extra ticks of the last instruction are taken out of the next frame.
+pub(super) struct Scheduler {
+  pacing: Pacing,
+  timing: Timing,
+  tick_budget: i64,
+  in_frame: bool, //The debugger can stop in the middle of a frame. The rest of it runs when we continue.
+  last_timestamp: Option<f64>,
+  pending_time: f64,  //Seconds of real time we still owe to the emulation.
+}
+
+impl Scheduler {
+  pub fn new(pacing: Pacing, timing: Timing) -> Self {
+    Self {
+      pacing,
+      timing,
+      tick_budget: 0,
+      in_frame: false,
+      last_timestamp: None,
+      pending_time: 0.0,
+    }
+  }
+
+  /// Call before running the instructions of a frame. Does nothing if the last frame wasn't finished.
+  pub fn start_frame(&mut self) {
+    if !self.in_frame {
+      self.tick_budget += self.timing.ticks_per_frame() as i64;
+      self.in_frame = true;
+    }
+  }
+
+  /// Counts down the ticks of the frame. Returns true once the frame is complete.
+  pub fn run_cycle(&mut self, clock_ticks: usize) -> bool {
+    self.tick_budget -= clock_ticks as i64;
+    self.in_frame = self.tick_budget > 0;
+    !self.in_frame
+  }
+
+  pub fn in_frame(&self) -> bool {
+    self.in_frame
+  }
+
+  /// Waits for the next animation frame without owing any time to the emulation. Used while paused.
+  pub async fn idle(&mut self) {
+    next_animation_frame().await;
+    self.last_timestamp = None;
+    self.pending_time = 0.0;
+  }
+
+  /// Waits for the right moment and returns how many frames should be emulated now.
+  pub async fn wait(&mut self, sound: &super::sound::Sound) -> u32 {
+    //A suspended AudioContext (browsers block audio until the user clicks something) has a frozen clock. Fall back to the display.
+    if self.pacing == Pacing::Audio && sound.is_running() {
+      //Sleep even when no wait is needed. On a machine slower than real time the sound never gets ahead,
+      //and without giving control back the page would stop drawing and taking input.
+      let wait = sound.time_until_ready();
+      sleep((wait * 1000.0).ceil() as i32).await;
+      self.last_timestamp = None;
+      1
+    } else {
+      let timestamp = next_animation_frame().await / 1000.0;
+      let frames_per_second = self.timing.frames_per_second as f64;
+      let elapsed = match self.last_timestamp {
+        Some(last) => timestamp - last,
+        None => 1.0 / frames_per_second,
+      };
+      self.last_timestamp = Some(timestamp);
+      self.pending_time += elapsed;
+      let frames = (self.pending_time * frames_per_second) as u32;
+      self.pending_time -= frames as f64 / frames_per_second;
+      if frames > MAX_FRAMES_PER_WAIT {
+        self.pending_time = 0.0;
+        MAX_FRAMES_PER_WAIT
+      } else {
+        frames
+      }
+    }
+  }
+}
+
+use wasm_bindgen::closure;
+
+/// Resolves on the next requestAnimationFrame, giving back its timestamp in milliseconds.
+async fn next_animation_frame() -> f64 {
+  let (sender, receiver) = futures::channel::oneshot::channel::<f64>();
+
+  let callback = closure::Closure::once_into_js(move |timestamp: f64| {
+    let _ = sender.send(timestamp);
+  });
+
+  web_sys::window().unwrap().request_animation_frame(callback.unchecked_ref()).unwrap();
+
+  receiver.await.unwrap_or(0.0)
+}
+
+/// A trick to get browsers to "sleep" by awaiting a set_timeout
+pub(super) async fn sleep(milliseconds: i32) {
+  let promise = js_sys::Promise::new(&mut |resolve, _| {
+    web_sys::window().unwrap().set_timeout_with_callback_and_timeout_and_arguments_0(&resolve, milliseconds).unwrap();
+  });
+  let _ = wasm_bindgen_futures::JsFuture::from(promise).await;
+}
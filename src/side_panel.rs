@@ -1,37 +1,145 @@
 use chips::fairchild_f8;
 
+const SHOWN_PORTS: [usize; 4] = [0, 1, 4, 5];
+
+// Values which changed since the last refresh are highlighted, and so is the scratchpad register the ISAR points to.
+
 pub struct SidePanel {
   registers: web_sys::HtmlCollection,
   memory: [u8; 0x40],
+  memory_classes: [&'static str; 0x40],
+  cpu_fields: Vec<Field>,
+  audio_status: Option<web_sys::Element>,
+  audio_text: String,
+}
+
+/// One value in the CPU state table. The cell is only touched when its text or highlight changes.
+struct Field {
+  element: Option<web_sys::Element>,
+  text: String,
+  changed: bool,
+}
+
+impl Field {
+  fn new(id: &str) -> Self {
+    Self {
+      element: get_element(id),
+      text: String::new(),
+      changed: false,
+    }
+  }
+
+  fn set(&mut self, text: String) {
+    let changed = self.text != text && !self.text.is_empty();
+    if let Some(element) = &self.element {
+      if self.text != text {
+        element.set_text_content(Some(&text));
+      }
+      if self.changed != changed {
+        element.set_class_name(if changed { "changed" } else { "" });
+      }
+    }
+    self.text = text;
+    self.changed = changed;
+  }
 }
 
 impl SidePanel {
   
   pub fn new() -> Self {
+    let mut cpu_fields = vec![];
+    for id in ["cpu_a", "cpu_w", "cpu_sign", "cpu_carry", "cpu_zero", "cpu_overflow", "cpu_icb", "cpu_isar", "cpu_pc0", "cpu_pc1", "cpu_dc0", "cpu_dc1"] {
+      cpu_fields.push(Field::new(id));
+    }
+    for port in SHOWN_PORTS {
+      cpu_fields.push(Field::new(&format!("port_out_{}", port)));
+      cpu_fields.push(Field::new(&format!("port_in_{}", port)));
+    }
     Self {
       registers: get_tr_list("registers"),
-      memory: [0; 0x40]
+      memory: [0; 0x40],
+      memory_classes: [""; 0x40],
+      cpu_fields,
+      audio_status: get_element("audio_status"),
+      audio_text: String::new(),
     }
   }
   
   pub fn print_memory(&mut self, board: &fairchild_f8::Board) {
     let tr_list = &self.registers;
     let regs = board.cpu.regs;
+    let isar = (board.cpu.isar & 0x3F) as usize;
     for high in 0_usize..=0x7 {
       for low in 0_usize..=0x07 {
         let address = low + high * 0o10;
-        if self.memory[address] != regs[address] {
-          self.memory[address] = regs[address];
+        let changed = self.memory[address] != regs[address];
+        let class = match (changed, address == isar) {
+          (true, true) => "changed isar",
+          (true, false) => "changed",
+          (false, true) => "isar",
+          (false, false) => "",
+        };
+        if changed || self.memory_classes[address] != class {
           let row_index = 1 + high as u32;
           let td_list = tr_list.item(row_index).expect("can't get tr").children();
           let col_index = low as u32 + 2;
           if let Some(td) = td_list.item(col_index) {
-            td.set_text_content(Some(&format!("{:02X}", regs[address])));
+            if changed {
+              td.set_text_content(Some(&format!("{:02X}", regs[address])));
+            }
+            td.set_class_name(class);
           }
+          self.memory[address] = regs[address];
+          self.memory_classes[address] = class;
         }
       }
     }
   }
+
+  /// The accumulator, the status flags, the ISAR, the program counters, the data counters, and the I/O ports.
+  /// Ports show what the CPU last wrote (out), and what the hardware answers (in).
+  pub fn print_cpu(&mut self, board: &fairchild_f8::Board) {
+    let cpu = &board.cpu;
+    let rom = &board.roms[0];
+    let flag = |bit: u8| if cpu.w & bit != 0 { "1".to_string() } else { "0".to_string() };
+    let mut texts = vec![
+      format!("{:02X}", cpu.a),
+      format!("{:02X}", cpu.w),
+      flag(0b00001),  //Sign
+      flag(0b00010),  //Carry
+      flag(0b00100),  //Zero
+      flag(0b01000),  //Overflow
+      flag(0b10000),  //Interrupt control bit
+      format!("{:02o}", cpu.isar & 0x3F),  //Octal, like the scratchpad table.
+      format!("{:04X}", rom.pc0),
+      format!("{:04X}", rom.pc1),
+      format!("{:04X}", rom.dc0),
+      format!("{:04X}", rom.dc1),
+    ];
+    for port in SHOWN_PORTS {
+      texts.push(format!("{:02X}", cpu.ports[port]));
+      texts.push(format!("{:02X}", board.ports[port]));
+    }
+    for (field, text) in self.cpu_fields.iter_mut().zip(texts) {
+      field.set(text);
+    }
+  }
+
+  pub fn print_audio_status(&mut self, latency: f64, underruns: u32) {
+    let text = format!("Latency: {:.0} ms, Underruns: {}", latency * 1000.0, underruns);
+    if self.audio_text != text {
+      if let Some(element) = &self.audio_status {
+        element.set_text_content(Some(&text));
+      }
+      self.audio_text = text;
+    }
+  }
+}
+
+fn get_element(id: &str) -> Option<web_sys::Element> {
+  let window = web_sys::window().expect("no global `window` exists");
+  let document = window.document().expect("should have a document on window");
+  document.get_element_by_id(id)
 }
 
 fn get_tr_list(table_id: &str) -> web_sys::HtmlCollection {
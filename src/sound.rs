@@ -1,97 +1,131 @@
 use chips::fairchild_f8;
+use super::scheduler;
+use super::tone;
+use std::collections::VecDeque;
 
-// The way this sound system works is by saving multiple buffers and feeding them to the browser at the right time.
+// The way this sound system works is by filling up buffers of buffer_size samples and queueing each one right after the previous one.
 //
 // | Sound audio_buffer 1 | Sound audio_buffer 2 | Sound audio_buffer 3 |
-//                  ^
-//                  |
-//              Ended event
+//           ^                                                        ^
+//           |                                                        |
+//      current_time                                           next_start_time
 //
-// At the ended event of sound audio_buffer 1, sound audio_buffer 2 should already start playing.
-// While sound audio_buffer 2 is playing, build up audio_buffer 3 and schedule it.
-// When sound audio_buffer 2 has ended, we start building up audio_buffer 4.
+// The distance between current_time and next_start_time is the latency. The scheduler decides when to run frames,
+// so the latency will drift. To keep it bounded, we apply dynamic rate control:
+// if the latency is below the target, each frame produces slightly more samples, and if above, slightly fewer.
+// The pitch change is a fraction of a percent, which nobody will hear.
 //
-// So this way, we are always planning for the next sound audio_buffer, while a current one is playing.
-// We should never be in a situation where nothing is playing.
-//
-// But what to do if we run into this situation? For example, when the browser is just started, there is no sound. Or if CPU busy, and we don't have enough time to compute the next audio_buffer?
-// Then this happens:
-// 1. There is nothing playing.
-// 2. There is no ending event.
-// 3. The only audio_buffer we have, is the current one.
-// So, how are we supposed to "jumpstart" this?
-// If we timeout from an ending event, we should rebuild audio_buffer 1 and 2 asap (don't sleep between 1 and 2).
-
-//cpu runs at 500 ns per clock tick
-const TICKS_PER_SECOND: u64 = 2_000_000; // Each tick takes 500 ns. 1 / 0.0000005 = 2_000_000
-const FRAMES_PER_SECOND: usize = 50;
+// If we run out of queued sound (CPU busy, tab was in background), that is an underrun. We restart the queue from the current time.
+// Slow devices can raise the latency and the number of buffers to get fewer underruns.
+
+const MAX_RATE_ADJUST: f64 = 0.005; //At most stretch the sound by 0.5%.
+const MIN_WAIT: f64 = 0.001;  //Even when there is room already, so the browser gets a turn.
+
+#[derive(Clone, Copy)]
+pub(super) struct AudioConfig {
+  pub latency_ms: u32,  //How much sound we try to keep queued.
+  pub buffer_count: u32,  //The most buffers we allow to be queued at once.
+  pub buffer_size: u32, //Samples per buffer. 0 means one frame worth of samples.
+}
+
+impl Default for AudioConfig {
+  fn default() -> Self {
+    Self {
+      latency_ms: 60,
+      buffer_count: 3,
+      buffer_size: 0,
+    }
+  }
+}
 
 pub(super) struct Sound {
   audio_context: web_sys::AudioContext,
-  audio_buffer: Vec<f32>,  //The size of this is sample_rate / frames per second to get the number of samples per frame. 48,000 samples rate / 50 frames per second = 960 samples per frame.
-  current_frequency: u64,
-  total_clock_ticks: u64,
-  ticks_since_freq_start: u64,  //Count how many ticks we have passed for the current frequency.
+  config: AudioConfig,
+  timing: scheduler::Timing,
+  volume: f32,  //0.0 to 1.0
+  audio_buffer: Vec<f32>,  //Samples not queued yet.
+  buffer_size: usize, //Normally 48,000 samples rate / 50 frames per second = 960 samples per frame.
+  tone: tone::ToneGenerator,
   sample_rate: u64, //Normally 48,000
-  start_time: f64,
-  refresh_count: usize,
-  current_audio: web_sys::AudioBufferSourceNode,
-  previous_audio: web_sys::AudioBufferSourceNode,
-  restarting: bool,
+  sample_tick: f64, //The clock tick where the next sample starts.
+  rate_adjust: f64,
+  next_start_time: f64,
+  queued_end_times: VecDeque<f64>,  //When each queued buffer finishes playing.
+  underruns: u32,
 }
 
 
 impl Sound {
-  pub fn new() -> Self {
+  pub fn new(config: AudioConfig, timing: scheduler::Timing, volume: f32) -> Self {
     let audio_context = web_sys::AudioContext::new().expect("Failed to create AudioContext object");
     let sample_rate = audio_context.sample_rate() as u64;
-    let start_time = audio_context.current_time();
-    let current_audio = audio_context.create_buffer_source().unwrap();
-    let previous_audio = audio_context.create_buffer_source().unwrap();
-    Self {
+    let next_start_time = audio_context.current_time();
+    let mut sound = Self {
       audio_context,
-      audio_buffer: vec![0.0; sample_rate as usize / FRAMES_PER_SECOND], //48,000 samples rate / 50 frames per second = 960 samples per frame.
-      current_frequency: 0,
-      total_clock_ticks: 0,
-      ticks_since_freq_start: 0,
+      config,
+      timing,
+      volume: volume.clamp(0.0, 1.0),
+      audio_buffer: Vec::with_capacity(sample_rate as usize),
+      buffer_size: 0,
+      tone: tone::ToneGenerator::new(timing.ticks_per_second),
       sample_rate,
-      start_time,
-      refresh_count: 0,
-      current_audio,
-      previous_audio,
-      restarting: false,
-    }
+      sample_tick: 0.0,
+      rate_adjust: 0.0,
+      next_start_time,
+      queued_end_times: VecDeque::new(),
+      underruns: 0,
+    };
+    sound.set_config(config);
+    sound
   }
 
-  /// Buffers sound. Returns true once we fill up the sound audio_buffer, so the refresh can occur.
-  pub fn run_cycle(&mut self, board: &fairchild_f8::Board, clock_ticks: usize) -> bool {
-    self.total_clock_ticks += clock_ticks as u64;
-    let audio_buffer_index = (self.total_clock_ticks * self.sample_rate / TICKS_PER_SECOND) as usize;
-    if audio_buffer_index >= self.audio_buffer.len() {
-      return true;
-    }
-    let freq = match board.read_port(5) >> 6 {
-      0b01 => 1000,
-      0b10 => 500,
-      0b11 => 120,
-      _ => 0,
+  pub fn set_config(&mut self, config: AudioConfig) {
+    self.config = AudioConfig {
+      latency_ms: config.latency_ms.clamp(10, 1000),
+      buffer_count: config.buffer_count.clamp(2, 16),
+      buffer_size: config.buffer_size.min(self.sample_rate as u32),
     };
-    if self.current_frequency != freq {
-      self.current_frequency = freq;
-      self.ticks_since_freq_start = 0;
+    self.buffer_size = if self.config.buffer_size == 0 {
+      (self.sample_rate / self.timing.frames_per_second) as usize
     } else {
-      self.ticks_since_freq_start += clock_ticks as u64;
-    }
-    if freq != 0 {
-      let audio_samples_since_freq_start = self.ticks_since_freq_start * self.sample_rate / TICKS_PER_SECOND;
-      let period = self.sample_rate / freq;
-      self.audio_buffer[audio_buffer_index] = if audio_samples_since_freq_start % period < period / 2 { 1.0 } else { -1.0 };
+      self.config.buffer_size.max(64) as usize
+    };
+    self.underruns = 0;
+  }
+
+  /// Buffers sound for the clock ticks that just passed. Every time a buffer fills up, it gets queued.
+  /// port_write is the port the instruction wrote, decoded before it ran.
+  pub fn run_cycle(&mut self, board: &fairchild_f8::Board, clock_ticks: usize, port_write: Option<u8>) -> Option<tone::ToneEvent> {
+    let event = self.tone.run_cycle(board, clock_ticks, port_write);
+    let ticks_per_sample = self.timing.ticks_per_second as f64 / (self.sample_rate as f64 * (1.0 + self.rate_adjust));
+    let total_ticks = self.tone.total_ticks() as f64;
+    while self.sample_tick + ticks_per_sample <= total_ticks {
+      let sample = self.tone.sample(self.sample_tick, self.sample_tick + ticks_per_sample);
+      self.audio_buffer.push(sample * self.volume);
+      self.sample_tick += ticks_per_sample;
+      if self.audio_buffer.len() >= self.buffer_size {
+        self.queue_buffer();
+      }
     }
-    false
+    self.tone.discard_before(self.sample_tick);
+    event
   }
-  
-  //Schedules the future audio to play. If no audio is playing currently, it will prepare the previous and current audio too.
-  pub async fn run_refresh_cycle(&mut self) {
+
+  /// Queues the filled buffer right after the previously queued sound.
+  fn queue_buffer(&mut self) {
+    let current_time = self.audio_context.current_time();
+    if !self.is_running() {
+      //The audio clock is frozen, so anything we queue would pile up. Throw the sound away.
+      self.next_start_time = current_time;
+      self.audio_buffer.clear();
+      return;
+    }
+    if self.next_start_time < current_time {
+      //We ran out of sound. Start again, leaving some room to build up the queue.
+      self.underruns += 1;
+      self.next_start_time = current_time + self.target_latency() / 2.0;
+    }
+
     let channel_buffer = self.audio_context.create_buffer(1, self.audio_buffer.len() as u32, self.sample_rate as f32).unwrap();
     channel_buffer.copy_to_channel(&self.audio_buffer, 0).unwrap();
 
@@ -101,81 +135,58 @@ impl Sound {
 
     //Connect our graph
     future_audio.connect_with_audio_node(&self.audio_context.destination()).unwrap();
-    
-    future_audio.start_with_when(self.start_time + self.refresh_count as f64 / FRAMES_PER_SECOND as f64).expect("Couldn't schedule sound"); //Schedule the buffered sound
-    
-    //When restarting, we do not sleep, or wait for anything. previous_audio should be playing now.
-    //So I should schedule current_audio to play without sleeping, so we can prepare the future audio.
-    if self.restarting {
-      self.restarting = false;
-      self.refresh_count += 1;
-      self.current_audio = future_audio;
-    } else {
-      if !finish_audio_or_timeout(&self.previous_audio).await {
-        //Oh no, we timed out.
-        //This means there is something wrong with our buffers.
-        //Lets reinitialize everything and rebuild the buffers.
-        self.refresh_count = 0;
-        self.previous_audio = future_audio;
-        self.restarting = true;
-      } else {
-        self.refresh_count += 1;
-        self.previous_audio = std::mem::replace(&mut self.current_audio, future_audio);
-      }
-    }
-    //Get ready for the next sound, cleaning the audio_buffer and reset total_clock_ticks to reset the audio_buffer_index.
-    self.audio_buffer.fill(0.0);
-    self.total_clock_ticks = 0;
-  }
-}
 
+    future_audio.start_with_when(self.next_start_time).expect("Couldn't schedule sound"); //Schedule the buffered sound
+    self.next_start_time += self.audio_buffer.len() as f64 / self.sample_rate as f64;
+    self.queued_end_times.push_back(self.next_start_time);
+    self.audio_buffer.clear();
+  }
 
-use futures::FutureExt; // for `.fuse()`
-async fn finish_audio_or_timeout(audio_node: &web_sys::AudioBufferSourceNode) -> bool {
-  let audio_event = finish_audio(audio_node).fuse();
-  let timeout = sleep(1000 * 2 / FRAMES_PER_SECOND as i32).fuse();  //Timeout 
+  /// Called once per frame. Applies the dynamic rate control.
+  pub fn run_refresh_cycle(&mut self) {
+    let current_time = self.audio_context.current_time();
+    while self.queued_end_times.front().is_some_and(|&end_time| end_time <= current_time) {
+      self.queued_end_times.pop_front();
+    }
 
-  futures::pin_mut!(audio_event, timeout);
+    //Too little queued means we should make more samples per tick.
+    let target_latency = self.target_latency();
+    let latency = self.next_start_time - current_time;
+    self.rate_adjust = ((target_latency - latency) / target_latency * MAX_RATE_ADJUST).clamp(-MAX_RATE_ADJUST, MAX_RATE_ADJUST);
+  }
 
-  futures::select! {
-    _ = audio_event => {
-      // The `ended` event was triggered
-      true
-    },
-    _ = timeout => {
-      // The timeout occurred
-      false
-    }
+  /// Seconds of sound that are queued and not played yet.
+  pub fn latency(&self) -> f64 {
+    (self.next_start_time - self.audio_context.current_time()).max(0.0)
   }
-}
 
-use wasm_bindgen::closure;
-use std::sync;
-use wasm_bindgen::JsCast; // for unchecked_ref()
+  pub fn target_latency(&self) -> f64 {
+    self.config.latency_ms as f64 / 1000.0
+  }
 
-// We are waiting for this sound to finish. This is used instead of sleeping. It finishes once it plays through the 1 / FRAMES_PER_SECOND duration.
-async fn finish_audio(audio_node: &web_sys::AudioBufferSourceNode) {
-  let (sender, receiver) = futures::channel::oneshot::channel::<()>();
-  let sender = sync::Arc::new(sync::Mutex::new(Some(sender)));
+  /// Seconds until there is room for more sound: the queue must be under the target latency, with fewer than buffer_count buffers.
+  /// Never less than MIN_WAIT. With a short latency or few buffers there is often room right away.
+  pub fn time_until_ready(&self) -> f64 {
+    let current_time = self.audio_context.current_time();
+    let queued: Vec<f64> = self.queued_end_times.iter().copied().filter(|&end_time| end_time > current_time).collect();
+    let buffer_wait = if queued.len() >= self.config.buffer_count as usize {
+      queued[queued.len() - self.config.buffer_count as usize] - current_time
+    } else {
+      0.0
+    };
+    let latency_wait = self.latency() - self.target_latency();
+    buffer_wait.max(latency_wait).max(MIN_WAIT)
+  }
 
-  let closure = closure::Closure::wrap(Box::new(move || {
-    let mut sender = sender.lock().unwrap();
-    if let Some(s) = sender.take() {
-      let _ = s.send(());
-    }
-  }) as Box<dyn FnMut()>);
+  pub fn total_ticks(&self) -> u64 {
+    self.tone.total_ticks()
+  }
 
-  audio_node.set_onended(Some(closure.as_ref().unchecked_ref()));
-  closure.forget();
+  pub fn underruns(&self) -> u32 {
+    self.underruns
+  }
 
-  let _ = receiver.await.unwrap();
+  pub fn is_running(&self) -> bool {
+    self.audio_context.state() == web_sys::AudioContextState::Running
+  }
 }
-
-/// A trick to get browsers to "sleep" by awaiting a set_timeout
-// This is used as a backup in case sound it not playing. We sleep instead of waiting for the sound to finish.
-async fn sleep(milliseconds: i32) {
-  let promise = js_sys::Promise::new(&mut |resolve, _| {
-    web_sys::window().unwrap().set_timeout_with_callback_and_timeout_and_arguments_0(&resolve, milliseconds).unwrap();
-  });
-  let _ = wasm_bindgen_futures::JsFuture::from(promise).await;
-}
\ No newline at end of file
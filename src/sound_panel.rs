@@ -0,0 +1,130 @@
+use wasm_bindgen::prelude::*;
+use std::collections::VecDeque;
+use super::tone;
+use super::scheduler;
+
+const OSCILLOSCOPE_FRAMES: u64 = 2;  //How many of the latest frames the oscilloscope shows.
+const LOG_LENGTH: usize = 16;
+
+/// Debug view of what the game asked the sound hardware to do.
+/// The oscilloscope is rendered from the same tone events the sound is made from, so it matches what was played.
+pub(super) struct SoundPanel {
+  timing: scheduler::Timing,
+  canvas_context: Option<web_sys::CanvasRenderingContext2d>,
+  canvas_size: (f64, f64),
+  tone_log: Option<web_sys::Element>,
+  events: VecDeque<tone::ToneEvent>,  //Events which can still be seen on the oscilloscope.
+  log: VecDeque<tone::ToneEvent>,
+  log_changed: bool,
+  last_drawn_tick: u64,
+}
+
+impl SoundPanel {
+  pub fn new(timing: scheduler::Timing) -> Self {
+    let window = web_sys::window().expect("no global `window` exists");
+    let document = window.document().expect("should have a document on window");
+    let mut canvas_size = (0.0, 0.0);
+    let canvas_context = document.get_element_by_id("oscilloscope").map(|element| {
+      let canvas: &web_sys::HtmlCanvasElement = element.dyn_ref().unwrap();
+      canvas_size = (canvas.width() as f64, canvas.height() as f64);
+      let canvas_context = canvas.get_context("2d").expect("the canvas should have a context").expect("the canvas should have a context");
+      canvas_context.dyn_into::<web_sys::CanvasRenderingContext2d>().unwrap()
+    });
+    //The log goes into the tbody.
+    let tone_log = document.get_element_by_id("tone_log").and_then(|table| table.get_elements_by_tag_name("tbody").item(0));
+    Self {
+      timing,
+      canvas_context,
+      canvas_size,
+      tone_log,
+      events: VecDeque::new(),
+      log: VecDeque::with_capacity(LOG_LENGTH + 1),
+      log_changed: true,
+      last_drawn_tick: u64::MAX,
+    }
+  }
+
+  pub fn log_tone(&mut self, event: tone::ToneEvent) {
+    //Without an oscilloscope nothing would ever take them off again.
+    if self.canvas_context.is_some() {
+      self.events.push_back(event);
+      self.trim_events(event.tick);
+    }
+    self.log.push_back(event);
+    if self.log.len() > LOG_LENGTH {
+      self.log.pop_front();
+    }
+    self.log_changed = true;
+  }
+
+  pub fn print(&mut self, total_ticks: u64) {
+    self.draw_oscilloscope(total_ticks);
+    if self.log_changed {
+      self.print_log(total_ticks);
+      self.log_changed = false;
+    }
+  }
+
+  fn draw_oscilloscope(&mut self, total_ticks: u64) {
+    if self.last_drawn_tick == total_ticks {
+      return;
+    }
+    self.last_drawn_tick = total_ticks;
+    self.trim_events(total_ticks);
+    let canvas_context = match &self.canvas_context {
+      Some(canvas_context) => canvas_context,
+      None => return,
+    };
+
+    let window_ticks = OSCILLOSCOPE_FRAMES * self.timing.ticks_per_frame();
+    let start_tick = total_ticks.saturating_sub(window_ticks);
+
+    let (width, height) = self.canvas_size;
+    let events: Vec<tone::ToneEvent> = self.events.iter().copied().collect();
+    let ticks_per_pixel = window_ticks as f64 / width;
+    let samples = tone::render(&events, self.timing.ticks_per_second, start_tick as f64, ticks_per_pixel, width as usize);
+
+    canvas_context.clear_rect(0.0, 0.0, width, height);
+    canvas_context.set_stroke_style(&JsValue::from("#00ce5a"));
+    canvas_context.begin_path();
+    for (x, sample) in samples.iter().enumerate() {
+      let y = (1.0 - *sample as f64) * (height - 2.0) / 2.0 + 1.0;
+      if x == 0 {
+        canvas_context.move_to(x as f64, y);
+      } else {
+        canvas_context.line_to(x as f64, y);
+      }
+    }
+    canvas_context.stroke();
+  }
+
+  /// Drops the events which ended before the oscilloscope window up to the tick.
+  /// The event which was playing when the window starts is kept.
+  fn trim_events(&mut self, tick: u64) {
+    let start_tick = tick.saturating_sub(OSCILLOSCOPE_FRAMES * self.timing.ticks_per_frame());
+    while self.events.len() > 1 && self.events[1].tick <= start_tick {
+      self.events.pop_front();
+    }
+  }
+
+  /// Newest tone first. The duration of the tone which is still playing is counted up to now.
+  fn print_log(&self, total_ticks: u64) {
+    let tbody = match &self.tone_log {
+      Some(tbody) => tbody,
+      None => return,
+    };
+    let mut html = String::new();
+    for (index, event) in self.log.iter().enumerate().rev() {
+      let (duration, suffix) = match self.log.get(index + 1) {
+        Some(next_event) => (next_event.tick - event.tick, ""),
+        None => (total_ticks - event.tick, "+"),
+      };
+      let freq = match tone::tone_frequency(event.tone) {
+        0 => "Off".to_string(),
+        freq => format!("{} Hz", freq),
+      };
+      html.push_str(&format!("<tr><td>{:02b}</td><td>{}</td><td>{}</td><td>{}{}</td></tr>", event.tone, freq, event.tick, duration, suffix));
+    }
+    tbody.set_inner_html(&html);
+  }
+}
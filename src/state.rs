@@ -0,0 +1,153 @@
+use chips::fairchild_f8;
+use super::cartridge;
+use super::checksum;
+use super::download;
+use super::loader::{LoadError, Roms};
+
+// A save state is what the console was doing at one moment, so a link can start a game from there.
+// It holds what the chips Board lets us set from outside: the CPU registers, the scratchpad, the program and data counters,
+// the output port latches and the cartridge RAM. VRAM can only be read, so the screen fills in again as the game redraws it.
+//
+// Numbers are little endian:
+//   0x00  8 bytes   "CHFSTATE"
+//   0x08  1 byte    Version, 1
+//   0x09  4 bytes   CRC-32 of the cartridge ROM, 0 without a cartridge
+//   0x0D  3 bytes   A, W, ISAR
+//   0x10  64 bytes  Scratchpad
+//   0x50  8 bytes   PC0, PC1, DC0, DC1
+//   0x58  2 bytes   Number of port latches, then the latches
+//   ...   2 bytes   Size of the cartridge RAM, then the RAM as it is saved
+
+const SIGNATURE: &[u8] = b"CHFSTATE";
+const VERSION: u8 = 1;
+
+#[derive(PartialEq, Debug)]
+struct State {
+  cartridge_crc32: u32,
+  cpu: [u8; 3], //A, W, ISAR
+  scratchpad: Vec<u8>,
+  counters: [u16; 4], //PC0, PC1, DC0, DC1
+  ports: Vec<u8>,
+  ram: Vec<u8>,
+}
+
+impl State {
+  fn to_bytes(&self) -> Vec<u8> {
+    let mut bytes = SIGNATURE.to_vec();
+    bytes.push(VERSION);
+    bytes.extend_from_slice(&self.cartridge_crc32.to_le_bytes());
+    bytes.extend_from_slice(&self.cpu);
+    bytes.extend_from_slice(&self.scratchpad);
+    for counter in self.counters {
+      bytes.extend_from_slice(&counter.to_le_bytes());
+    }
+    for block in [&self.ports, &self.ram] {
+      bytes.extend_from_slice(&(block.len() as u16).to_le_bytes());
+      bytes.extend_from_slice(block);
+    }
+    bytes
+  }
+
+  fn parse(bytes: &[u8]) -> Option<Self> {
+    if !bytes.starts_with(SIGNATURE) || bytes.get(0x08) != Some(&VERSION) {
+      return None;
+    }
+    let read_u16 = |offset: usize| Some(u16::from_le_bytes(bytes.get(offset..offset + 2)?.try_into().ok()?));
+    let cartridge_crc32 = u32::from_le_bytes(bytes.get(0x09..0x0D)?.try_into().ok()?);
+    let cpu = bytes.get(0x0D..0x10)?.try_into().ok()?;
+    let scratchpad = bytes.get(0x10..0x50)?.to_vec();
+    let counters = [read_u16(0x50)?, read_u16(0x52)?, read_u16(0x54)?, read_u16(0x56)?];
+    let ports_size = read_u16(0x58)? as usize;
+    let ports = bytes.get(0x5A..0x5A + ports_size)?.to_vec();
+    let ram_offset = 0x5A + ports_size;
+    let ram_size = read_u16(ram_offset)? as usize;
+    let ram = bytes.get(ram_offset + 2..ram_offset + 2 + ram_size)?.to_vec();
+    Some(Self { cartridge_crc32, cpu, scratchpad, counters, ports, ram })
+  }
+}
+
+fn cartridge_crc32(roms: &Roms) -> u32 {
+  roms.cartridge.as_deref().map_or(0, checksum::crc32)
+}
+
+/// Downloads the state of the running console.
+pub(super) fn save(roms: &Roms, board: &fairchild_f8::Board, cartridge: &cartridge::Cartridge) {
+  let rom = &board.roms[0];
+  let state = State {
+    cartridge_crc32: cartridge_crc32(roms),
+    cpu: [board.cpu.a, board.cpu.w, board.cpu.isar],
+    scratchpad: board.cpu.regs.to_vec(),
+    counters: [rom.pc0, rom.pc1, rom.dc0, rom.dc1],
+    ports: board.cpu.ports.to_vec(),
+    ram: cartridge.save_ram(board).unwrap_or_default(),
+  };
+  download::download("state.chfs", &state.to_bytes());
+}
+
+/// Puts the console where the state was saved. The cartridge has to be the same one.
+pub(super) fn load(bytes: &[u8], roms: &Roms, board: &mut fairchild_f8::Board, cartridge: &mut cartridge::Cartridge) -> Result<(), LoadError> {
+  let state = State::parse(bytes).ok_or(LoadError::BrokenState)?;
+  let crc32 = cartridge_crc32(roms);
+  if state.cartridge_crc32 != crc32 {
+    return Err(LoadError::StateForOtherRom(state.cartridge_crc32, crc32));
+  }
+  [board.cpu.a, board.cpu.w, board.cpu.isar] = state.cpu;
+  for (register, &value) in board.cpu.regs.iter_mut().zip(&state.scratchpad) {
+    *register = value;
+  }
+  //Each memory chip keeps its own copy of the counters.
+  let [pc0, pc1, dc0, dc1] = state.counters;
+  for rom in board.roms.iter_mut() {
+    rom.pc0 = pc0;
+    rom.pc1 = pc1;
+    rom.dc0 = dc0;
+    rom.dc1 = dc1;
+  }
+  for (latch, &value) in board.cpu.ports.iter_mut().zip(&state.ports) {
+    *latch = value;
+  }
+  if !state.ram.is_empty() {
+    cartridge.load_ram(board, &state.ram);
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn state() -> State {
+    State {
+      cartridge_crc32: 0x12345678,
+      cpu: [1, 0b10010, 0o17],
+      scratchpad: (0..64).collect(),
+      counters: [0x0802, 0x0123, 0x2800, 0],
+      ports: vec![0x40, 0, 0, 0, 0x7F, 0xC0],
+      ram: vec![0xAA; 128],
+    }
+  }
+
+  #[test]
+  fn comes_back_the_same() {
+    let bytes = state().to_bytes();
+    assert_eq!(&bytes[..9], b"CHFSTATE\x01");
+    assert_eq!(State::parse(&bytes), Some(state()));
+  }
+
+  #[test]
+  fn no_ram() {
+    let state = State { ram: vec![], ..state() };
+    assert_eq!(State::parse(&state.to_bytes()), Some(state));
+  }
+
+  #[test]
+  fn cut_short_or_other_files_are_refused() {
+    let bytes = state().to_bytes();
+    assert_eq!(State::parse(&bytes[..bytes.len() - 1]), None);
+    assert_eq!(State::parse(&bytes[..0x20]), None);
+    let mut other_version = bytes.clone();
+    other_version[8] = 2;
+    assert_eq!(State::parse(&other_version), None);
+    assert_eq!(State::parse(b"CHANNEL F"), None);
+  }
+}
@@ -0,0 +1,192 @@
+use chips::fairchild_f8;
+use std::collections::VecDeque;
+
+// The tone generator is controlled by bits 6 and 7 of port 5. Each combination selects a square wave.
+//
+// Instead of asking the port what the tone is whenever we need a sample, we keep a list of the tone changes,
+// stamped with the clock tick they happened on. A sample covers around 40 clock ticks, so a sample is the average of
+// the square wave over those ticks. This way a beep shorter than a sample still comes out, just quieter.
+//
+// The board doesn't tell us about port writes, so we look at port 5 after every instruction.
+// The instruction is decoded before it runs, so we know when it was an OUT or OUTS to port 5. Its write is on the bus
+// in the cycle before the last, which fetches the next opcode, and the event is stamped there.
+// A multi-cycle instruction doesn't move the tone change to its end.
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub(super) struct ToneEvent {
+  pub tick: u64,
+  pub tone: u8, //Bits 6 and 7 of port 5, shifted down.
+}
+
+const FETCH_TICKS: usize = 4;  //The short cycle every instruction ends with, fetching the next opcode.
+const TONE_PORT: u8 = 5;
+
+pub(super) fn tone_frequency(tone: u8) -> u64 {
+  match tone {
+    0b01 => 1000,
+    0b10 => 500,
+    0b11 => 120,
+    _ => 0,
+  }
+}
+
+pub(super) struct ToneGenerator {
+  events: VecDeque<ToneEvent>,  //Always has at least one event, the tone which is currently playing.
+  total_ticks: u64,
+  ticks_per_second: u64,
+}
+
+impl ToneGenerator {
+  pub fn new(ticks_per_second: u64) -> Self {
+    Self::from_events(&[], ticks_per_second)
+  }
+
+  /// Builds the generator from a known list of events, sorted by tick. Before the first event, there is silence.
+  pub fn from_events(events: &[ToneEvent], ticks_per_second: u64) -> Self {
+    let mut all_events = VecDeque::with_capacity(events.len() + 1);
+    all_events.push_back(ToneEvent { tick: 0, tone: 0 });
+    all_events.extend(events.iter().copied());
+    Self {
+      total_ticks: all_events.back().unwrap().tick,
+      events: all_events,
+      ticks_per_second,
+    }
+  }
+
+  /// Records a tone event if the instruction which just ran changed the tone.
+  /// port_write is the port the instruction wrote, decoded before it ran.
+  pub fn run_cycle(&mut self, board: &fairchild_f8::Board, clock_ticks: usize, port_write: Option<u8>) -> Option<ToneEvent> {
+    let tone = board.read_port(TONE_PORT) >> 6;
+    let event = self.change(tone, clock_ticks, port_write == Some(TONE_PORT));
+    self.total_ticks += clock_ticks as u64;
+    event
+  }
+
+  /// A tone change by the instruction starting at total_ticks. Without a write to the port we can't tell when, so it's the end.
+  fn change(&mut self, tone: u8, clock_ticks: usize, written: bool) -> Option<ToneEvent> {
+    if self.events.back().unwrap().tone != tone {
+      let offset = if written { clock_ticks.saturating_sub(FETCH_TICKS) } else { clock_ticks };
+      let event = ToneEvent { tick: self.total_ticks + offset as u64, tone };
+      self.events.push_back(event);
+      Some(event)
+    } else {
+      None
+    }
+  }
+
+  pub fn total_ticks(&self) -> u64 {
+    self.total_ticks
+  }
+
+  /// The average of the waveform between two ticks, from -1.0 to 1.0.
+  pub fn sample(&self, from_tick: f64, to_tick: f64) -> f32 {
+    if to_tick <= from_tick {
+      return 0.0;
+    }
+    let mut total = 0.0;
+    for (index, event) in self.events.iter().enumerate() {
+      let start = (event.tick as f64).max(from_tick);
+      let end = match self.events.get(index + 1) {
+        Some(next_event) => (next_event.tick as f64).min(to_tick),
+        None => to_tick,
+      };
+      if end > start {
+        total += square_wave_area(event, start, end, self.ticks_per_second);
+      }
+    }
+    (total / (to_tick - from_tick)) as f32
+  }
+
+  /// Forgets the events which ended before this tick. They can no longer affect any sample.
+  pub fn discard_before(&mut self, tick: f64) {
+    while self.events.len() > 1 && self.events[1].tick as f64 <= tick {
+      self.events.pop_front();
+    }
+  }
+}
+
+/// Renders samples straight from a list of tone events.
+pub(super) fn render(events: &[ToneEvent], ticks_per_second: u64, start_tick: f64, ticks_per_sample: f64, sample_count: usize) -> Vec<f32> {
+  let generator = ToneGenerator::from_events(events, ticks_per_second);
+  (0..sample_count).map(|index| {
+    let from_tick = start_tick + index as f64 * ticks_per_sample;
+    generator.sample(from_tick, from_tick + ticks_per_sample)
+  }).collect()
+}
+
+/// The area under the square wave of one tone, between two ticks. The wave starts high at the tick of the event.
+fn square_wave_area(event: &ToneEvent, from_tick: f64, to_tick: f64, ticks_per_second: u64) -> f64 {
+  let freq = tone_frequency(event.tone);
+  if freq == 0 {
+    return 0.0;
+  }
+  let half_period = ticks_per_second as f64 / freq as f64 / 2.0;
+  let from_phase = (from_tick - event.tick as f64) / half_period;
+  let to_phase = (to_tick - event.tick as f64) / half_period;
+  (triangle(to_phase) - triangle(from_phase)) * half_period
+}
+
+/// The running area of a square wave, counted in half periods. Goes up during the high half and back down during the low half.
+fn triangle(phase: f64) -> f64 {
+  let position = phase % 2.0;
+  if position < 1.0 { position } else { 2.0 - position }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const TICKS_PER_SECOND: u64 = 2_000_000;
+
+  #[test]
+  fn silence_before_the_first_event() {
+    let samples = render(&[], TICKS_PER_SECOND, 0.0, 40.0, 10);
+    assert!(samples.iter().all(|&sample| sample == 0.0));
+  }
+
+  #[test]
+  fn square_wave_averages_over_a_sample() {
+    //1000 Hz: high for 1000 ticks, then low for 1000 ticks.
+    let events = [ToneEvent { tick: 0, tone: 0b01 }];
+    let samples = render(&events, TICKS_PER_SECOND, 0.0, 500.0, 4);
+    assert_eq!(samples, vec![1.0, 1.0, -1.0, -1.0]);
+    //A sample across the edge is half high and half low.
+    let samples = render(&events, TICKS_PER_SECOND, 750.0, 500.0, 1);
+    assert_eq!(samples, vec![0.0]);
+  }
+
+  #[test]
+  fn event_inside_a_sample_is_counted_from_its_tick() {
+    let events = [ToneEvent { tick: 120, tone: 0b01 }];
+    let samples = render(&events, TICKS_PER_SECOND, 0.0, 40.0, 4);
+    assert_eq!(samples, vec![0.0, 0.0, 0.0, 1.0]);
+    let samples = render(&events, TICKS_PER_SECOND, 100.0, 40.0, 1);
+    assert_eq!(samples, vec![0.5]);
+  }
+
+  #[test]
+  fn short_beep_comes_out_quieter() {
+    let events = [ToneEvent { tick: 0, tone: 0b01 }, ToneEvent { tick: 10, tone: 0 }];
+    let samples = render(&events, TICKS_PER_SECOND, 0.0, 40.0, 2);
+    assert_eq!(samples, vec![0.25, 0.0]);
+  }
+
+  #[test]
+  fn rendering_is_reproducible() {
+    let events = [ToneEvent { tick: 37, tone: 0b10 }, ToneEvent { tick: 5000, tone: 0b11 }, ToneEvent { tick: 90000, tone: 0 }];
+    let first = render(&events, TICKS_PER_SECOND, 0.0, 41.67, 2500);
+    let second = render(&events, TICKS_PER_SECOND, 0.0, 41.67, 2500);
+    assert_eq!(first, second);
+  }
+
+  #[test]
+  fn write_is_stamped_before_the_fetch() {
+    let mut generator = ToneGenerator::new(TICKS_PER_SECOND);
+    generator.total_ticks = 100;
+    let event = generator.change(0b01, 10, true).unwrap();
+    assert_eq!(event.tick, 106);
+    let event = generator.change(0b10, 10, false).unwrap();
+    assert_eq!(event.tick, 110);
+    assert_eq!(generator.change(0b10, 10, true), None);
+  }
+}
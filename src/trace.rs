@@ -0,0 +1,182 @@
+use chips::fairchild_f8;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU32, Ordering};
+use super::disassembler;
+use super::download;
+
+// Logging through the log crate, shown in the browser console. Messages have a level and a category, the log target,
+// and both can be chosen in the page. Every instruction is logged at the trace level in the cpu category,
+// so that one is best left off unless needed. It is the same as the download, see Entry.
+//
+// Separately, the last few instructions can be kept in memory with the full CPU state, and downloaded as text.
+// That costs far less than logging them, so it can stay on while playing, to see what led up to a crash.
+//
+// The text has one instruction per line, as it is right before running, and is kept stable so traces can be compared
+// with other emulators (see trace_diff):
+//
+//   CYC=0000001234 PC=0802 OP=2555 A=00 W=00 IS=00 DC0=0800 DC1=0000 PC1=0000 R=<64 registers in hex> ;CI 0x55
+//
+// CYC counts clock ticks. OP is the bytes of the instruction. Everything after ; is only for reading.
+
+//A line of the download is about 230 characters, so the most instructions kept make a text of about 23 MB.
+pub(super) const MAX_SIZE: usize = 100000;
+pub(super) const CATEGORIES: [&str; 5] = ["cpu", "ports", "sound", "debugger", "loader"];
+
+static CATEGORY_MASK: AtomicU32 = AtomicU32::new(!0);  //Bit per category, in the order of CATEGORIES.
+static LOGGER: ConsoleLogger = ConsoleLogger;
+
+struct ConsoleLogger;
+
+impl log::Log for ConsoleLogger {
+  fn enabled(&self, metadata: &log::Metadata) -> bool {
+    //Messages of other crates have their own targets. Those are only filtered by level.
+    let mask = CATEGORY_MASK.load(Ordering::Relaxed);
+    CATEGORIES.iter().position(|category| *category == metadata.target()).is_none_or(|index| mask & (1 << index) != 0)
+  }
+
+  fn log(&self, record: &log::Record) {
+    if !self.enabled(record.metadata()) {
+      return;
+    }
+    let message = wasm_bindgen::JsValue::from(format!("[{}] {}", record.target(), record.args()));
+    match record.level() {
+      log::Level::Error => web_sys::console::error_1(&message),
+      log::Level::Warn => web_sys::console::warn_1(&message),
+      log::Level::Info => web_sys::console::info_1(&message),
+      log::Level::Debug | log::Level::Trace => web_sys::console::debug_1(&message),
+    }
+  }
+
+  fn flush(&self) {}
+}
+
+/// Sends log messages to the console, from this level up: off, error, warn, info, debug or trace.
+pub(super) fn init(level: &str) {
+  if log::set_logger(&LOGGER).is_ok() {
+    set_level(level);
+  }
+}
+
+pub(super) fn set_level(level: &str) {
+  log::set_max_level(level.parse().unwrap_or(log::LevelFilter::Warn));
+}
+
+pub(super) fn set_category(category: &str, enabled: bool) {
+  if let Some(index) = CATEGORIES.iter().position(|name| *name == category) {
+    if enabled {
+      CATEGORY_MASK.fetch_or(1 << index, Ordering::Relaxed);
+    } else {
+      CATEGORY_MASK.fetch_and(!(1 << index), Ordering::Relaxed);
+    }
+  }
+}
+
+/// The CPU state right before an instruction runs.
+#[derive(Clone, Copy)]
+struct Entry {
+  ticks: u64, //Clock ticks since the page loaded, or the last power cycle.
+  pc0: u16,
+  bytes: [u8; 3], //The instruction, and what may follow it.
+  a: u8,
+  w: u8,
+  isar: u8,
+  dc0: u16,
+  dc1: u16,
+  pc1: u16,
+  regs: [u8; 64],
+}
+
+impl Entry {
+  fn new(board: &fairchild_f8::Board, ticks: u64) -> Self {
+    let pc0 = board.roms[0].pc0;
+    Self {
+      ticks,
+      pc0,
+      bytes: [board.read_memory(pc0), board.read_memory(pc0.wrapping_add(1)), board.read_memory(pc0.wrapping_add(2))],
+      a: board.cpu.a,
+      w: board.cpu.w,
+      isar: board.cpu.isar & 0x3F,
+      dc0: board.roms[0].dc0,
+      dc1: board.roms[0].dc1,
+      pc1: board.roms[0].pc1,
+      regs: board.cpu.regs,
+    }
+  }
+}
+
+impl std::fmt::Display for Entry {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    let instruction = disassembler::decode(|address| self.bytes[address.wrapping_sub(self.pc0) as usize % 3], self.pc0);
+    let opcode: Vec<String> = self.bytes[..instruction.size as usize].iter().map(|byte| format!("{:02X}", byte)).collect();
+    let regs: Vec<String> = self.regs.iter().map(|reg| format!("{:02X}", reg)).collect();
+    write!(
+      f, "CYC={:010} PC={:04X} OP={} A={:02X} W={:02X} IS={:02X} DC0={:04X} DC1={:04X} PC1={:04X} R={} ;{}",
+      self.ticks, self.pc0, opcode.join(""), self.a, self.w, self.isar, self.dc0, self.dc1, self.pc1, regs.join(""), instruction.text,
+    )
+  }
+}
+
+pub(super) struct Trace {
+  entries: VecDeque<Entry>,
+  size: usize, //How many instructions to keep. 0 keeps none.
+  ticks: u64,
+}
+
+impl Trace {
+  pub fn new(size: usize) -> Self {
+    Self { entries: VecDeque::new(), size: size.min(MAX_SIZE), ticks: 0 }
+  }
+
+  pub fn set_size(&mut self, size: usize) {
+    let size = size.min(MAX_SIZE);
+    self.size = size;
+    while self.entries.len() > size {
+      self.entries.pop_front();
+    }
+  }
+
+  /// Called right before every instruction.
+  pub fn run_cycle(&mut self, board: &fairchild_f8::Board) {
+    let logging = log::log_enabled!(target: "cpu", log::Level::Trace);
+    if self.size == 0 && !logging {
+      return;
+    }
+    let entry = Entry::new(board, self.ticks);
+    if logging {
+      log::trace!(target: "cpu", "{}", entry);
+    }
+    if self.size > 0 {
+      if self.entries.len() >= self.size {
+        self.entries.pop_front();
+      }
+      self.entries.push_back(entry);
+    }
+  }
+
+  /// Called with the clock ticks every instruction took.
+  pub fn add_ticks(&mut self, clock_ticks: usize) {
+    self.ticks += clock_ticks as u64;
+  }
+
+  /// Starts counting from zero again, for a new power on.
+  pub fn reset(&mut self) {
+    self.entries.clear();
+    self.ticks = 0;
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.entries.is_empty()
+  }
+
+  pub fn text(&self) -> String {
+    let mut text = String::new();
+    for entry in &self.entries {
+      text.push_str(&format!("{}\n", entry));
+    }
+    text
+  }
+
+  pub fn download(&self) {
+    download::download("trace.txt", self.text().as_bytes());
+  }
+}
@@ -0,0 +1,202 @@
+// Compares our trace with one from another Channel F emulator, to find where the CPU first goes a different way.
+//
+// Reference logs come in many shapes, so parsing is loose. A line is an instruction if it has a PC. Fields are KEY=VALUE
+// or KEY:VALUE, in hex except for the cycle count, which is decimal. Anything after ; is a comment.
+// Common other names are understood, like PC0, ISAR or CYCLES.
+// R=<hex> holds all registers, R12=2F just one. Only fields both logs have are compared, and of OP only the opcode.
+// Cycle counts are compared from the first instruction on, since the logs rarely started counting at the same time.
+//
+// Our trace only keeps the last instructions, so the reference log can start later than ours. Comparing starts at the
+// first of our lines which matches the first line of the reference log.
+
+const CONTEXT: usize = 5; //Lines shown before the difference.
+
+struct Line<'a> {
+  number: usize,  //In the file, from 1.
+  text: &'a str,
+  fields: Vec<(String, u64)>,
+}
+
+impl Line<'_> {
+  fn field(&self, key: &str) -> Option<u64> {
+    self.fields.iter().find(|(name, _)| name == key).map(|(_, value)| *value)
+  }
+}
+
+fn parse(log: &str) -> Vec<Line<'_>> {
+  log.lines().enumerate().filter_map(|(index, text)| {
+    let fields = parse_fields(text);
+    if fields.iter().any(|(key, _)| key == "PC") {
+      Some(Line { number: index + 1, text: text.trim_end(), fields })
+    } else {
+      None
+    }
+  }).collect()
+}
+
+fn parse_fields(text: &str) -> Vec<(String, u64)> {
+  let text = text.split(';').next().unwrap_or("");
+  let mut fields = vec![];
+  for token in text.split(|character: char| character.is_whitespace() || character == ',') {
+    let (key, value) = match token.split_once('=').or_else(|| token.split_once(':')) {
+      Some(pair) => pair,
+      None => continue,
+    };
+    let key = match key.to_uppercase().as_str() {
+      "PC0" => "PC".to_string(),
+      "ACC" => "A".to_string(),
+      "ISAR" => "IS".to_string(),
+      "DC" => "DC0".to_string(),
+      "CYCLE" | "CYCLES" | "CLK" | "TICKS" => "CYC".to_string(),
+      "OPCODE" => "OP".to_string(),
+      key => key.to_string(),
+    };
+    let value = value.trim_start_matches('$').trim_start_matches("0x");
+    match key.as_str() {
+      "CYC" => {
+        if let Ok(value) = value.parse() {
+          fields.push((key, value));
+        }
+      },
+      "OP" => {
+        if let Some(opcode) = value.get(..2).and_then(|opcode| u64::from_str_radix(opcode, 16).ok()) {
+          fields.push((key, opcode));
+        }
+      },
+      "R" => {
+        for (register, pair) in value.as_bytes().chunks(2).enumerate() {
+          if let Some(reg) = std::str::from_utf8(pair).ok().and_then(|pair| u64::from_str_radix(pair, 16).ok()) {
+            fields.push((format!("R{}", register), reg));
+          }
+        }
+      },
+      _ => {
+        if let Ok(value) = u64::from_str_radix(value, 16) {
+          fields.push((key, value));
+        }
+      },
+    }
+  }
+  fields
+}
+
+/// The fields of two lines which are in both and differ, with both values.
+fn differences(ours: &Line, reference: &Line, our_start: u64, reference_start: u64) -> Vec<(String, u64, u64)> {
+  ours.fields.iter().filter_map(|(key, value)| {
+    let reference_value = reference.field(key)?;
+    let (value, reference_value) = if key == "CYC" {
+      (value.wrapping_sub(our_start), reference_value.wrapping_sub(reference_start))
+    } else {
+      (*value, reference_value)
+    };
+    if value != reference_value { Some((key.clone(), value, reference_value)) } else { None }
+  }).collect()
+}
+
+/// A report of where the logs first differ, with the lines leading up to it.
+pub(super) fn compare(ours: &str, reference: &str) -> String {
+  let ours = parse(ours);
+  let reference = parse(reference);
+  if ours.is_empty() {
+    return "Our trace has no instructions.".to_string();
+  }
+  let first = match reference.first() {
+    Some(first) => first,
+    None => return "The reference log has no lines with a PC.".to_string(),
+  };
+  let start = ours.iter().position(|line| differences(line, first, 0, 0).iter().all(|(key, _, _)| key == "CYC")).unwrap_or(0);
+  let our_start = ours[start].field("CYC").unwrap_or(0);
+  let reference_start = first.field("CYC").unwrap_or(0);
+
+  let pairs: Vec<(&Line, &Line)> = ours[start..].iter().zip(reference.iter()).collect();
+  for (index, (our_line, reference_line)) in pairs.iter().enumerate() {
+    let differences = differences(our_line, reference_line, our_start, reference_start);
+    if differences.is_empty() {
+      continue;
+    }
+    let mut report = format!(
+      "First difference after {} matching instructions, at our line {} and reference line {}:\n",
+      index, our_line.number, reference_line.number,
+    );
+    for (key, value, reference_value) in &differences {
+      if key == "CYC" {
+        report.push_str(&format!("  {}: {} here, {} in the reference (counted from the first instruction)\n", key, value, reference_value));
+      } else {
+        report.push_str(&format!("  {}: {:02X} here, {:02X} in the reference\n", key, value, reference_value));
+      }
+    }
+    let context = &pairs[index.saturating_sub(CONTEXT)..=index];
+    report.push_str("\nOurs:\n");
+    for (line, _) in context {
+      report.push_str(&format!("{:>8}  {}\n", line.number, line.text));
+    }
+    report.push_str("\nReference:\n");
+    for (_, line) in context {
+      report.push_str(&format!("{:>8}  {}\n", line.number, line.text));
+    }
+    return report;
+  }
+  let longer = match (ours.len() - start).cmp(&reference.len()) {
+    std::cmp::Ordering::Greater => " Our trace goes on further.",
+    std::cmp::Ordering::Less => " The reference log goes on further.",
+    std::cmp::Ordering::Equal => "",
+  };
+  format!("No differences in {} instructions, from our line {}.{}", pairs.len(), ours[start].number, longer)
+}
+
+pub(super) fn show(report: &str) {
+  let document = web_sys::window().unwrap().document().unwrap();
+  if let Some(element) = document.get_element_by_id("trace_compare") {
+    element.set_text_content(Some(report));
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const OURS: &str = "\
+CYC=0000000100 PC=0000 OP=1A A=00 W=00 IS=00 DC0=0000 R=0011 ;DI
+CYC=0000000104 PC=0001 OP=70 A=00 W=00 IS=00 DC0=0000 R=0011 ;CLR
+CYC=0000000108 PC=0002 OP=B0 A=00 W=00 IS=00 DC0=0000 R=0011 ;OUTS 0
+CYC=0000000112 PC=0003 OP=2A0800 A=00 W=00 IS=00 DC0=0000 R=0011 ;DCI 0x0800
+CYC=0000000124 PC=0006 OP=16 A=00 W=00 IS=00 DC0=0800 R=0011 ;LM
+";
+
+  #[test]
+  fn same_trace_matches() {
+    assert_eq!(compare(OURS, OURS), "No differences in 5 instructions, from our line 1.");
+  }
+
+  #[test]
+  fn other_formats_are_understood() {
+    let reference = "Some header\nPC:0000 ACC:00 cycles:0 r1=11\nPC:0001 ACC:00 cycles:4\nPC:0002 ACC:00 cycles:8\n";
+    assert_eq!(compare(OURS, reference), "No differences in 3 instructions, from our line 1. Our trace goes on further.");
+  }
+
+  #[test]
+  fn first_difference_is_reported() {
+    let reference = OURS.replace("CYC=0000000124 PC=0006 OP=16 A=00 W=00 IS=00 DC0=0800", "CYC=0000000124 PC=0006 OP=16 A=00 W=00 IS=00 DC0=0801");
+    let report = compare(OURS, &reference);
+    assert!(report.starts_with("First difference after 4 matching instructions, at our line 5 and reference line 5:\n  DC0: 800 here, 801 in the reference\n"), "{}", report);
+  }
+
+  #[test]
+  fn cycles_count_from_the_first_instruction() {
+    let reference = "PC=0000 CYC=5\nPC=0001 CYC=9\nPC=0002 CYC=14\n";
+    let report = compare(OURS, reference);
+    assert!(report.contains("  CYC: 8 here, 9 in the reference (counted from the first instruction)"), "{}", report);
+  }
+
+  #[test]
+  fn reference_can_start_later() {
+    let reference: String = OURS.lines().skip(2).map(|line| format!("{}\n", line)).collect();
+    assert_eq!(compare(OURS, &reference), "No differences in 3 instructions, from our line 3.");
+  }
+
+  #[test]
+  fn empty_logs() {
+    assert_eq!(compare("", OURS), "Our trace has no instructions.");
+    assert_eq!(compare(OURS, "nothing here"), "The reference log has no lines with a PC.");
+  }
+}
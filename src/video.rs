@@ -1,21 +1,72 @@
 use chips::fairchild_f8;
 use wasm_bindgen::prelude::*;
 
+/// The colors the TV shows. Indexed by the two color bits of a pixel, (bit0, bit1) = (1, 0) being index 1.
+#[derive(Clone, Copy)]
+pub(super) struct Palette {
+  colors: [&'static str; 4],  //Index 0 is not a color. The background shows through.
+  backgrounds: [&'static str; 4],
+  white: &'static str,  //On a black background, every color turns white.
+}
+
+impl Palette {
+  pub const DEFAULT: Palette = Palette {
+    colors: ["", "#4a3cf6", "#ff3052", "#00ce5a"],  //Blue, Red, Green
+    backgrounds: ["#000000", "#e6e2e6", "#cdd2ff", "#94ffa4"],  //Black, Gray, Light blue, Light green
+    white: "#FFFFFF",
+  };
+  pub const VIVID: Palette = Palette {
+    colors: ["", "#0000ff", "#ff0000", "#00ff00"],
+    backgrounds: ["#000000", "#d0d0d0", "#c0c0ff", "#a0ffa0"],
+    white: "#FFFFFF",
+  };
+  /// The default colors by their luminance, like a black and white TV.
+  pub const GRAYSCALE: Palette = Palette {
+    colors: ["", "#555555", "#727272", "#838383"],
+    backgrounds: ["#000000", "#e4e4e4", "#d6d6d6", "#d5d5d5"],
+    white: "#FFFFFF",
+  };
+
+  pub fn from_param(param: Option<&String>) -> Self {
+    match param.map(|p| p.as_str()) {
+      Some("vivid") => Palette::VIVID,
+      Some("grayscale") | Some("greyscale") => Palette::GRAYSCALE,
+      _ => Palette::DEFAULT,
+    }
+  }
+}
+
+fn color_index((bit0, bit1): (bool, bool)) -> usize {
+  (bit0 as usize) | ((bit1 as usize) << 1)
+}
+
+type Cell = ((bool, bool), (bool, bool)); //(color, background). Each consists of two bits, which sets the color.
+
 pub(super) struct Video {
   canvas_context: js_sys::Object,
-  memory: [[((bool, bool),(bool,bool)); 64]; 128],  //(color, background). Each consists of two bits, which sets the color.
+  palette: Palette,
+  memory: [[Cell; 64]; 128],
 }
 
 impl Video {
-  pub fn new() -> Self {
+  /// The canvas is made scale times bigger than in the page.
+  pub fn new(palette: Palette, scale: u32) -> Self {
     let window = web_sys::window().expect("no global `window` exists");
     let document = window.document().expect("should have a document on window");
     let dummy = document.get_element_by_id("canvas").expect("the canvas is missing");
     let canvas: &web_sys::HtmlCanvasElement = dummy.dyn_ref().unwrap();
+    if scale > 1 {
+      canvas.set_width(canvas.width() * scale);
+      canvas.set_height(canvas.height() * scale);
+    }
     let canvas_context = canvas.get_context("2d").expect("the canvas should have a context").expect("the canvas should have a context");
     //let canvas_context: &web_sys::CanvasRenderingContext2d = dummy.dyn_ref().unwrap();
+    if scale > 1 {
+      let _ = canvas_context.dyn_ref::<web_sys::CanvasRenderingContext2d>().unwrap().scale(scale as f64, scale as f64);
+    }
     Self {
-      canvas_context: canvas_context,
+      canvas_context,
+      palette,
       memory: [[((false, false),(false, false)); 64]; 128],
     }
   }
@@ -26,12 +77,7 @@ impl Video {
     for y in 2..=59 {
       
       let background = get_background_bits(board, y);
-      let html_background = match background {
-        (true, true) => "#94ffa4",    //Light green
-        (false, true) => "#cdd2ff",   //Light blue
-        (true, false) => "#e6e2e6",   //Gray
-        (false, false) => "#000000",  //Black
-      };
+      let html_background = self.palette.backgrounds[color_index(background)];
 
       for x in 20..=127 - 4 { //Lots of pixels are not displayed on the TV..
         let color = get_pixel(board, x, y);
@@ -39,14 +85,11 @@ impl Video {
           self.memory[x][y] = (color, background);
           let html_color = wasm_bindgen::JsValue::from(if background == (false, false) && color != (false, false) {
             //black and white converts all colors to white
-            "#FFFFFF" //white
+            self.palette.white
+          } else if color == (false, false) {
+            html_background
           } else {
-            match color {
-              (true, true) => "#00ce5a",  //Green
-              (false, true) => "#ff3052", //Red
-              (true, false) => "#4a3cf6", //Blue
-              (false, false) => html_background,
-            }
+            self.palette.colors[color_index(color)]
           });
           let canvas_context: &web_sys::CanvasRenderingContext2d = self.canvas_context.dyn_ref().unwrap();
           canvas_context.set_fill_style(&html_color);
@@ -73,6 +116,11 @@ fn get_background_bits(board: &fairchild_f8::Board, y: usize) -> (bool, bool) {
   (bit0, bit1)
 }
 
+/// The two color bits of a pixel in VRAM, as a number from 0 to 3.
+pub(super) fn pixel(board: &fairchild_f8::Board, x: usize, y: usize) -> u8 {
+  color_index(get_pixel(board, x, y)) as u8
+}
+
 fn get_pixel(board: &fairchild_f8::Board, x: usize, y: usize) -> (bool, bool) {
   let address = x + y * 128;
   let bit0 = if address < 0x1000 {
@@ -0,0 +1,248 @@
+use chips::fairchild_f8;
+use super::video;
+
+// Watchpoints stop the program right after an instruction reads or writes a location, optionally only for one value.
+//
+// The board doesn't tell us what an instruction touched, so before each instruction runs we decode it and note what it will
+// read and write: scratchpad registers, memory through DC0 (which includes cartridge RAM), and I/O ports. After it ran,
+// we compare that to the watchpoints. Cartridge RAM behind I/O ports, like the 2102, is watched through its ports.
+//
+// The CPU can't read VRAM. A VRAM watchpoint is a pixel, caught when its color changes.
+
+const VRAM_WIDTH: u16 = 128;
+
+#[derive(Clone, Copy, PartialEq)]
+pub(super) enum Space {
+  Scratchpad,
+  Memory,
+  Port,
+  Vram, //Address is y * 128 + x
+}
+
+impl Space {
+  pub fn from_name(name: &str) -> Option<Self> {
+    match name {
+      "scratchpad" => Some(Space::Scratchpad),
+      "memory" => Some(Space::Memory),
+      "port" => Some(Space::Port),
+      "vram" => Some(Space::Vram),
+      _ => None,
+    }
+  }
+
+  fn describe(&self, address: u16) -> String {
+    match self {
+      Space::Scratchpad => format!("R{}", address),
+      Space::Memory => format!("{:04X}", address),
+      Space::Port => format!("Port {:02X}", address),
+      Space::Vram => format!("Pixel {},{}", address % VRAM_WIDTH, address / VRAM_WIDTH),
+    }
+  }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub(super) enum Access {
+  Read,
+  Write,
+  ReadWrite,
+}
+
+impl Access {
+  pub fn from_name(name: &str) -> Option<Self> {
+    match name {
+      "r" => Some(Access::Read),
+      "w" => Some(Access::Write),
+      "rw" => Some(Access::ReadWrite),
+      _ => None,
+    }
+  }
+
+  fn name(&self) -> &'static str {
+    match self {
+      Access::Read => "read",
+      Access::Write => "write",
+      Access::ReadWrite => "read/write",
+    }
+  }
+
+  fn covers(&self, access: Access) -> bool {
+    *self == Access::ReadWrite || *self == access
+  }
+}
+
+pub(super) struct Watchpoint {
+  pub space: Space,
+  pub address: u16,
+  pub access: Access,
+  pub value: Option<u8>,  //Only stop when this is the value read or written.
+  pub hits: u32,
+  last_pixel: Option<u8>,
+}
+
+impl Watchpoint {
+  pub fn new(space: Space, address: u16, access: Access, value: Option<u8>) -> Self {
+    //Nothing reads VRAM, so only writes make sense there.
+    let access = if space == Space::Vram { Access::Write } else { access };
+    Self { space, address, access, value, hits: 0, last_pixel: None }
+  }
+
+  pub fn describe(&self) -> String {
+    match self.value {
+      Some(value) => format!("{} {} = {:02X}", self.space.describe(self.address), self.access.name(), value),
+      None => format!("{} {}", self.space.describe(self.address), self.access.name()),
+    }
+  }
+
+  /// Checks the instruction which just ran. Returns true if we should stop.
+  pub fn check(&mut self, board: &fairchild_f8::Board, accesses: &[(Space, u16, Access)]) -> bool {
+    let hit = if self.space == Space::Vram {
+      let pixel = video::pixel(board, (self.address % VRAM_WIDTH) as usize, (self.address / VRAM_WIDTH) as usize);
+      let changed = self.last_pixel.is_some_and(|last_pixel| last_pixel != pixel);
+      self.last_pixel = Some(pixel);
+      changed && self.value.is_none_or(|value| value == pixel)
+    } else {
+      accesses.iter().any(|&(space, address, access)| {
+        space == self.space && address == self.address && self.access.covers(access)
+          && self.value.is_none_or(|value| value == read(board, space, address))
+      })
+    };
+    if hit {
+      self.hits += 1;
+    }
+    hit
+  }
+}
+
+/// The value at a location, as it is after the instruction.
+fn read(board: &fairchild_f8::Board, space: Space, address: u16) -> u8 {
+  match space {
+    Space::Scratchpad => board.cpu.regs[address as usize & 0x3F],
+    Space::Memory => board.read_memory(address),
+    Space::Port => board.read_port(address as u8),
+    Space::Vram => video::pixel(board, (address % VRAM_WIDTH) as usize, (address / VRAM_WIDTH) as usize),
+  }
+}
+
+/// What the instruction at PC0 is going to read and write. Has to be called before it runs, while ISAR and DC0 are still as it sees them.
+pub(super) fn decode_accesses(board: &fairchild_f8::Board) -> Vec<(Space, u16, Access)> {
+  let rom = &board.roms[0];
+  accesses(|address| board.read_memory(address), rom.pc0, rom.dc0, board.cpu.isar)
+}
+
+fn accesses(read: impl Fn(u16) -> u8, pc0: u16, dc0: u16, isar: u8) -> Vec<(Space, u16, Access)> {
+  let opcode = read(pc0);
+  let isar = (isar & 0x3F) as u16;
+  //Registers 12 - 14 in the low nibble of an opcode mean the register the ISAR points to.
+  let register = |nibble: u8| if nibble >= 12 { isar } else { nibble as u16 };
+  let scratchpad = |address: u16, access: Access| (Space::Scratchpad, address, access);
+  let low = opcode & 0x0F;
+  match opcode {
+    0x00..=0x03 => vec![scratchpad(12 + low as u16, Access::Read)], //LR A,KU/KL/QU/QL
+    0x04..=0x07 => vec![scratchpad(8 + low as u16, Access::Write)], //LR KU/KL/QU/QL,A
+    0x08 => vec![scratchpad(12, Access::Write), scratchpad(13, Access::Write)], //LR K,P
+    0x09 | 0x0C => vec![scratchpad(12, Access::Read), scratchpad(13, Access::Read)], //LR P,K and PK
+    0x0D | 0x0F => vec![scratchpad(14, Access::Read), scratchpad(15, Access::Read)], //LR P0,Q and LR DC,Q
+    0x0E => vec![scratchpad(14, Access::Write), scratchpad(15, Access::Write)], //LR Q,DC
+    0x10 => vec![scratchpad(10, Access::Read), scratchpad(11, Access::Read)], //LR DC,H
+    0x11 => vec![scratchpad(10, Access::Write), scratchpad(11, Access::Write)], //LR H,DC
+    0x16 | 0x88..=0x8D => vec![(Space::Memory, dc0, Access::Read)],  //LM, AM, AMD, NM, OM, XM, CM
+    0x17 => vec![(Space::Memory, dc0, Access::Write)],  //ST
+    0x1D => vec![scratchpad(9, Access::Read)],  //LR W,J
+    0x1E => vec![scratchpad(9, Access::Write)], //LR J,W
+    0x26 => vec![(Space::Port, read(pc0.wrapping_add(1)) as u16, Access::Read)], //IN
+    0x27 => vec![(Space::Port, read(pc0.wrapping_add(1)) as u16, Access::Write)],  //OUT
+    0x30..=0x3E => vec![scratchpad(register(low), Access::Read), scratchpad(register(low), Access::Write)], //DS
+    0x40..=0x4E => vec![scratchpad(register(low), Access::Read)], //LR A,r
+    0x50..=0x5E => vec![scratchpad(register(low), Access::Write)],  //LR r,A
+    0xA0..=0xAF => vec![(Space::Port, low as u16, Access::Read)], //INS
+    0xB0..=0xBF => vec![(Space::Port, low as u16, Access::Write)],  //OUTS
+    0xC0..=0xCE | 0xD0..=0xDE | 0xE0..=0xEE | 0xF0..=0xFE => vec![scratchpad(register(low), Access::Read)], //AS, ASD, XS, NS
+    _ => vec![],
+  }
+}
+
+/// The port the instruction at PC0 is going to write, if it is an OUT or OUTS. Cheaper than decode_accesses,
+/// for the hardware which has to know about every write, even of the same value.
+pub(super) fn port_write(board: &fairchild_f8::Board) -> Option<u8> {
+  port_written(|address| board.read_memory(address), board.roms[0].pc0)
+}
+
+fn port_written(read: impl Fn(u16) -> u8, pc0: u16) -> Option<u8> {
+  match read(pc0) {
+    0x27 => Some(read(pc0.wrapping_add(1))), //OUT
+    opcode @ 0xB0..=0xBF => Some(opcode & 0x0F),  //OUTS
+    _ => None,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const PC0: u16 = 0x0802;
+  const DC0: u16 = 0x2810;
+
+  /// What the instruction does, with ISAR at 0o25 (21).
+  fn decode(bytes: &[u8]) -> Vec<(Space, u16, Access)> {
+    accesses(|address| bytes.get(address.wrapping_sub(PC0) as usize).copied().unwrap_or(0xFF), PC0, DC0, 0o25)
+  }
+
+  #[test]
+  fn memory_through_dc0() {
+    assert!(decode(&[0x16]) == vec![(Space::Memory, DC0, Access::Read)]);  //LM
+    assert!(decode(&[0x17]) == vec![(Space::Memory, DC0, Access::Write)]); //ST
+    assert!(decode(&[0x88]) == vec![(Space::Memory, DC0, Access::Read)]);  //AM
+    assert!(decode(&[0x8C]) == vec![(Space::Memory, DC0, Access::Read)]);  //XM
+    assert!(decode(&[0x8D]) == vec![(Space::Memory, DC0, Access::Read)]);  //CM
+  }
+
+  #[test]
+  fn scratchpad_through_isar() {
+    //(IS), (IS)+ and (IS)- all use the register ISAR points to, before it moves.
+    for low in 0x0C..=0x0E {
+      assert!(decode(&[0x40 | low]) == vec![(Space::Scratchpad, 0o25, Access::Read)]); //LR A,(IS)
+      assert!(decode(&[0x50 | low]) == vec![(Space::Scratchpad, 0o25, Access::Write)]);  //LR (IS),A
+      assert!(decode(&[0xC0 | low]) == vec![(Space::Scratchpad, 0o25, Access::Read)]); //AS (IS)
+      assert!(decode(&[0x30 | low]) == vec![(Space::Scratchpad, 0o25, Access::Read), (Space::Scratchpad, 0o25, Access::Write)]);  //DS (IS)
+    }
+    //The ISAR is 6 bits.
+    assert!(accesses(|_| 0x4C, PC0, DC0, 0xC5) == vec![(Space::Scratchpad, 5, Access::Read)]);
+  }
+
+  #[test]
+  fn scratchpad_by_number() {
+    assert!(decode(&[0x4B]) == vec![(Space::Scratchpad, 11, Access::Read)]); //LR A,r11
+    assert!(decode(&[0x50]) == vec![(Space::Scratchpad, 0, Access::Write)]); //LR r0,A
+    assert!(decode(&[0x00]) == vec![(Space::Scratchpad, 12, Access::Read)]); //LR A,KU
+    assert!(decode(&[0x07]) == vec![(Space::Scratchpad, 15, Access::Write)]);  //LR QL,A
+    assert!(decode(&[0x1D]) == vec![(Space::Scratchpad, 9, Access::Read)]);  //LR W,J
+    //15 in the low nibble is no register, so 0x4F touches nothing.
+    assert!(decode(&[0x4F]).is_empty());
+  }
+
+  #[test]
+  fn ports() {
+    assert!(decode(&[0x27, 0x21]) == vec![(Space::Port, 0x21, Access::Write)]);  //OUT 0x21
+    assert!(decode(&[0x26, 0x20]) == vec![(Space::Port, 0x20, Access::Read)]); //IN 0x20
+    assert!(decode(&[0xB5]) == vec![(Space::Port, 5, Access::Write)]); //OUTS 5
+    assert!(decode(&[0xA1]) == vec![(Space::Port, 1, Access::Read)]);  //INS 1
+  }
+
+  #[test]
+  fn calls() {
+    assert!(decode(&[0x0C]) == vec![(Space::Scratchpad, 12, Access::Read), (Space::Scratchpad, 13, Access::Read)]); //PK
+    assert!(decode(&[0x28, 0x00, 0xD0]).is_empty());  //PI 0x00D0 only moves the program counters.
+    assert!(decode(&[0x1C]).is_empty());  //POP
+  }
+
+  #[test]
+  fn port_writes() {
+    let read = |bytes: &'static [u8]| move |address: u16| bytes.get(address.wrapping_sub(PC0) as usize).copied().unwrap_or(0);
+    assert_eq!(port_written(read(&[0x27, 0x21]), PC0), Some(0x21)); //OUT
+    assert_eq!(port_written(read(&[0xB0]), PC0), Some(0)); //OUTS 0
+    assert_eq!(port_written(read(&[0xBF]), PC0), Some(15));  //OUTS 15
+    assert_eq!(port_written(read(&[0x26, 0x21]), PC0), None);  //IN
+    assert_eq!(port_written(read(&[0xA4]), PC0), None);  //INS
+    assert_eq!(port_written(read(&[0x17]), PC0), None);  //ST
+  }
+}